@@ -0,0 +1,489 @@
+use crate::tree_export::format_size;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+};
+use xz2::read::XzDecoder;
+
+pub const DEFAULT_MAX_EXTRACTED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+pub const DEFAULT_MAX_ENTRIES: usize = 200_000;
+/// Uncompressed to compressed size; source trees rarely exceed 20, zip bombs reach thousands.
+pub const DEFAULT_MAX_COMPRESSION_RATIO: u64 = 200;
+
+/// Below this many bytes written the ratio is not checked, so tiny but repetitive archives pass.
+const RATIO_CHECK_THRESHOLD: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractLimits {
+    pub max_total_size: u64,
+    pub max_entries: usize,
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_total_size: DEFAULT_MAX_EXTRACTED_SIZE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_compression_ratio: DEFAULT_MAX_COMPRESSION_RATIO,
+        }
+    }
+}
+
+/// Recognises archives by file name; `.crate` files are gzipped tarballs.
+pub fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") || name.ends_with(".crate") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+        Some(ArchiveKind::TarXz)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+/// Extracts `path` into `dest`, rejecting the whole archive if any entry would land outside
+/// `dest` (`../`, absolute paths, links pointing out) or if it exceeds `limits`. Links that stay
+/// inside are not created since parsing skips them anyway. Nothing is left in `dest` on failure.
+pub fn extract_archive(
+    path: &Path,
+    kind: ArchiveKind,
+    dest: &Path,
+    limits: &ExtractLimits,
+) -> Result<()> {
+    fs::create_dir_all(dest)?;
+
+    let result = extract_checked(path, kind, dest, limits);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(dest);
+    }
+    result
+}
+
+/// The archive comment of a zip file; GitHub stores the commit SHA there.
+pub fn zip_comment(path: &Path) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let archive = zip::ZipArchive::new(BufReader::new(file))?;
+    Ok(String::from_utf8_lossy(archive.comment()).to_string())
+}
+
+fn extract_checked(
+    path: &Path,
+    kind: ArchiveKind,
+    dest: &Path,
+    limits: &ExtractLimits,
+) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let compressed_size = file.metadata()?.len();
+    let mut budget = Budget::new(limits, compressed_size);
+    let reader = BufReader::new(file);
+
+    match kind {
+        ArchiveKind::Zip => unpack_zip(reader, dest, &mut budget)
+            .with_context(|| format!("Extracting zip archive {}", path.display())),
+        ArchiveKind::Tar => unpack_tar(reader, dest, &mut budget),
+        ArchiveKind::TarGz => unpack_tar(GzDecoder::new(reader), dest, &mut budget),
+        ArchiveKind::TarXz => unpack_tar(XzDecoder::new(reader), dest, &mut budget),
+    }
+}
+
+fn unpack_zip<R: Read + io::Seek>(reader: R, dest: &Path, budget: &mut Budget) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_string();
+        budget.add_entry()?;
+
+        let relative = safe_relative_path(Path::new(&name)).with_context(|| {
+            format!("Unsafe archive entry '{}': path escapes the archive", name)
+        })?;
+        let target = dest.join(&relative);
+
+        if entry.is_symlink() {
+            let mut link_target = String::new();
+            entry.by_ref().take(4096).read_to_string(&mut link_target)?;
+            check_link(relative.parent(), Path::new(&link_target), &name)?;
+            continue;
+        }
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+
+        let ratio_limit = budget.limits.max_compression_ratio;
+        if entry.compressed_size() > 0
+            && entry.size() > RATIO_CHECK_THRESHOLD
+            && entry.size() / entry.compressed_size() > ratio_limit
+        {
+            return Err(anyhow::anyhow!(
+                "Archive entry '{}' expands more than {}x; refusing a likely zip bomb",
+                name,
+                ratio_limit
+            ));
+        }
+
+        // The header sizes checked above can lie, so the copy itself stops at the ratio.
+        let entry_limit = entry
+            .compressed_size()
+            .saturating_mul(ratio_limit)
+            .max(RATIO_CHECK_THRESHOLD);
+        let written = write_entry(&mut entry, &target, budget, entry_limit)?;
+        if written > entry_limit {
+            return Err(anyhow::anyhow!(
+                "Archive entry '{}' expands more than {}x; refusing a likely zip bomb",
+                name,
+                ratio_limit
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn unpack_tar<R: Read>(reader: R, dest: &Path, budget: &mut Budget) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries().context("Reading tar archive")? {
+        let mut entry = entry.context("Reading tar archive")?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        budget.add_entry()?;
+
+        let relative = safe_relative_path(&entry.path()?).with_context(|| {
+            format!("Unsafe archive entry '{}': path escapes the archive", name)
+        })?;
+        let target = dest.join(&relative);
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => fs::create_dir_all(&target)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                write_entry(&mut entry, &target, budget, u64::MAX)?;
+            }
+            tar::EntryType::Symlink | tar::EntryType::Link => {
+                let link_target = entry.link_name()?.unwrap_or_default().into_owned();
+                // Hard link names are relative to the archive root, symlinks to their directory.
+                let link_dir = match entry.header().entry_type() {
+                    tar::EntryType::Link => None,
+                    _ => relative.parent(),
+                };
+                check_link(link_dir, &link_target, &name)?;
+            }
+            // PAX/GNU headers are consumed by `entries`; devices, fifos and the like are skipped.
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies at most one byte past `entry_limit` and what `budget` has left, so an entry over
+/// either is caught without writing all of it. Returns the bytes written.
+fn write_entry<R: Read>(
+    entry: &mut R,
+    target: &Path,
+    budget: &mut Budget,
+    entry_limit: u64,
+) -> Result<u64> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Sizes in headers can lie, so count what is actually written.
+    let allowed = budget.remaining().min(entry_limit);
+    let mut out = File::create(target)?;
+    let written = io::copy(&mut entry.take(allowed.saturating_add(1)), &mut out)?;
+    budget.add_bytes(written)?;
+    Ok(written)
+}
+
+/// The entry path with `.` removed, or `None` if it is absolute or climbs out with `..`.
+pub fn safe_relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Rejects a link whose `target`, resolved from `link_dir` (the archive root when `None`), ends
+/// up outside the archive.
+fn check_link(link_dir: Option<&Path>, target: &Path, name: &str) -> Result<()> {
+    let escapes = || {
+        anyhow::anyhow!(
+            "Unsafe archive entry '{}': link points outside the archive",
+            name
+        )
+    };
+
+    if target.has_root() || matches!(target.components().next(), Some(Component::Prefix(_))) {
+        return Err(escapes());
+    }
+
+    let mut depth = link_dir.map_or(0, |dir| dir.components().count()) as i64;
+    for component in target.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return Err(escapes());
+        }
+    }
+    Ok(())
+}
+
+/// Running totals checked against the limits while extracting.
+struct Budget<'a> {
+    limits: &'a ExtractLimits,
+    compressed_size: u64,
+    entries: usize,
+    written: u64,
+}
+
+impl<'a> Budget<'a> {
+    fn new(limits: &'a ExtractLimits, compressed_size: u64) -> Self {
+        Self {
+            limits,
+            compressed_size,
+            entries: 0,
+            written: 0,
+        }
+    }
+
+    fn add_entry(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(anyhow::anyhow!(
+                "Archive has more than {} entries",
+                self.limits.max_entries
+            ));
+        }
+        Ok(())
+    }
+
+    /// Most bytes the archive may expand to before it counts as a zip bomb.
+    fn ratio_cap(&self) -> u64 {
+        self.compressed_size
+            .max(1)
+            .saturating_mul(self.limits.max_compression_ratio)
+            .max(RATIO_CHECK_THRESHOLD)
+    }
+
+    /// Bytes that can still be written before the size or the ratio limit is crossed.
+    fn remaining(&self) -> u64 {
+        self.limits
+            .max_total_size
+            .min(self.ratio_cap())
+            .saturating_sub(self.written)
+    }
+
+    fn add_bytes(&mut self, bytes: u64) -> Result<()> {
+        self.written += bytes;
+        if self.written > self.limits.max_total_size {
+            return Err(anyhow::anyhow!(
+                "Archive expands to more than {}",
+                format_size(self.limits.max_total_size)
+            ));
+        }
+
+        if self.written > self.ratio_cap() {
+            return Err(anyhow::anyhow!(
+                "Archive expands more than {}x; refusing a likely zip bomb",
+                self.limits.max_compression_ratio
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{zip_bytes, TempDir};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    /// A tar entry: name, type, link target and content. Names are written into the header
+    /// as-is, since `tar::Builder` refuses the unsafe ones these tests need.
+    type TarEntry<'a> = (&'a str, tar::EntryType, &'a str, &'a [u8]);
+
+    fn tar_bytes(entries: &[TarEntry]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, kind, link, content) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append(&header, *content).expect("append tar entry");
+        }
+        builder.into_inner().expect("finish tar")
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(bytes).expect("compress");
+        encoder.finish().expect("finish gzip")
+    }
+
+    /// Extracts `bytes` saved as `name` into `<tmp>/out`, which must be gone again on failure.
+    fn extract(tmp: &TempDir, name: &str, bytes: &[u8], limits: &ExtractLimits) -> Result<()> {
+        let path = tmp.path().join(name);
+        fs::write(&path, bytes).unwrap();
+        let dest = tmp.path().join("out");
+        let kind = archive_kind(&path).expect("archive name");
+        let result = extract_archive(&path, kind, &dest, limits);
+        if result.is_err() {
+            assert!(!dest.exists(), "{} left files behind", name);
+        }
+        result
+    }
+
+    fn error_of(result: Result<()>) -> String {
+        format!("{:#}", result.expect_err("extraction should fail"))
+    }
+
+    #[test]
+    fn extracts_a_plain_archive() {
+        let tmp = TempDir::new();
+        let zip = zip_bytes(&[("r/", b""), ("r/src/main.rs", b"fn main() {}")], None);
+        extract(&tmp, "a.zip", &zip, &ExtractLimits::default()).unwrap();
+        let main = tmp.path().join("out/r/src/main.rs");
+        assert_eq!(fs::read_to_string(main).unwrap(), "fn main() {}");
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_destination() {
+        let limits = ExtractLimits::default();
+        for name in ["../evil.txt", "a/../../evil.txt", "/tmp/evil.txt"] {
+            let tmp = TempDir::new();
+            let zip = zip_bytes(&[(name, b"x")], None);
+            let error = error_of(extract(&tmp, "a.zip", &zip, &limits));
+            assert!(
+                error.contains("path escapes the archive"),
+                "{}: {}",
+                name,
+                error
+            );
+
+            let tar = tar_bytes(&[(name, tar::EntryType::Regular, "", b"x")]);
+            let error = error_of(extract(&tmp, "a.tar", &tar, &limits));
+            assert!(
+                error.contains("path escapes the archive"),
+                "{}: {}",
+                name,
+                error
+            );
+            assert!(!tmp.path().join("evil.txt").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_links_pointing_outside() {
+        let limits = ExtractLimits::default();
+        let escaping = [
+            ("a/link", tar::EntryType::Symlink, "../../outside"),
+            ("a/link", tar::EntryType::Symlink, "/etc/passwd"),
+            ("a/hard", tar::EntryType::Link, "../outside"),
+        ];
+        for (name, kind, target) in escaping {
+            let tmp = TempDir::new();
+            let tar = tar_bytes(&[(name, kind, target, b"")]);
+            let error = error_of(extract(&tmp, "a.tar", &tar, &limits));
+            assert!(
+                error.contains("link points outside"),
+                "{}: {}",
+                target,
+                error
+            );
+        }
+
+        let tmp = TempDir::new();
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_symlink("a/link", "../../outside", SimpleFileOptions::default())
+            .unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        let error = error_of(extract(&tmp, "a.zip", &zip, &limits));
+        assert!(error.contains("link points outside"), "{}", error);
+
+        // Links that stay inside are passed over rather than refused.
+        let tmp = TempDir::new();
+        let tar = tar_bytes(&[
+            ("a/b.txt", tar::EntryType::Regular, "", b"b"),
+            ("a/link", tar::EntryType::Symlink, "../a/b.txt", b""),
+            ("hard", tar::EntryType::Link, "a/b.txt", b""),
+        ]);
+        extract(&tmp, "a.tar", &tar, &limits).unwrap();
+        assert!(!tmp.path().join("out/a/link").exists());
+    }
+
+    #[test]
+    fn enforces_entry_and_size_limits() {
+        let tmp = TempDir::new();
+        let limits = ExtractLimits {
+            max_entries: 2,
+            ..ExtractLimits::default()
+        };
+        let zip = zip_bytes(&[("a", b"a"), ("b", b"b"), ("c", b"c")], None);
+        let error = error_of(extract(&tmp, "a.zip", &zip, &limits));
+        assert!(error.contains("more than 2 entries"), "{}", error);
+
+        let limits = ExtractLimits {
+            max_total_size: 1000,
+            ..ExtractLimits::default()
+        };
+        let content = vec![b'x'; 600];
+        let zip = zip_bytes(&[("a", &content), ("b", &content)], None);
+        let error = error_of(extract(&tmp, "a.zip", &zip, &limits));
+        assert!(error.contains("expands to more than"), "{}", error);
+
+        let tar = gzip(&tar_bytes(&[
+            ("a", tar::EntryType::Regular, "", &content),
+            ("b", tar::EntryType::Regular, "", &content),
+        ]));
+        let error = error_of(extract(&tmp, "a.tar.gz", &tar, &limits));
+        assert!(error.contains("expands to more than"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_compression_ratio_bombs() {
+        let tmp = TempDir::new();
+        let limits = ExtractLimits::default();
+        let zeros = vec![0u8; 2 * RATIO_CHECK_THRESHOLD as usize];
+
+        let zip = zip_bytes(&[("bomb", &zeros)], None);
+        let error = error_of(extract(&tmp, "a.zip", &zip, &limits));
+        assert!(error.contains("zip bomb"), "{}", error);
+
+        // Tar headers give no compressed size, so only the copy itself can notice.
+        let tar = gzip(&tar_bytes(&[("bomb", tar::EntryType::Regular, "", &zeros)]));
+        let error = error_of(extract(&tmp, "a.tar.gz", &tar, &limits));
+        assert!(error.contains("zip bomb"), "{}", error);
+    }
+}
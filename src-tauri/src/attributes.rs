@@ -0,0 +1,131 @@
+use gix::bstr::{BStr, ByteSlice};
+use gix::glob::{pattern::Case, wildmatch, Pattern};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+const ATTRIBUTES_FILENAME: &str = ".gitattributes";
+
+/// How `.gitattributes` marks a file for GitHub Linguist, which leaves such files out of diffs
+/// and language statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinguistMark {
+    Generated,
+    Vendored,
+}
+
+impl LinguistMark {
+    pub fn description(self) -> &'static str {
+        match self {
+            LinguistMark::Generated => "marked linguist-generated in .gitattributes",
+            LinguistMark::Vendored => "marked linguist-vendored in .gitattributes",
+        }
+    }
+}
+
+/// One `.gitattributes` line setting or clearing a linguist attribute.
+struct Rule {
+    pattern: Pattern,
+    generated: Option<bool>,
+    vendored: Option<bool>,
+}
+
+/// `.gitattributes` files read on demand while a tree is traversed, once per directory.
+#[derive(Default)]
+pub struct LinguistAttributes {
+    rules: RefCell<HashMap<PathBuf, Rc<Vec<Rule>>>>,
+}
+
+impl LinguistAttributes {
+    /// The mark of the file at `path` according to the `.gitattributes` files from `root` down
+    /// to its directory. Like in git, deeper files and later lines win.
+    pub fn mark(&self, path: &Path, root: &Path) -> Option<LinguistMark> {
+        let relative = path.strip_prefix(root).ok()?;
+        let mut generated = false;
+        let mut vendored = false;
+
+        let mut dir = root.to_path_buf();
+        let components: Vec<_> = relative.components().collect();
+        for (depth, component) in components.iter().enumerate() {
+            let below: Vec<_> = components[depth..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let below = below.join("/");
+            let basename_start = below.rfind('/').map(|pos| pos + 1);
+
+            for rule in self.rules_in(&dir).iter() {
+                let matches = rule.pattern.matches_repo_relative_path(
+                    BStr::new(below.as_bytes()),
+                    basename_start,
+                    Some(false),
+                    Case::Sensitive,
+                    wildmatch::Mode::NO_MATCH_SLASH_LITERAL,
+                );
+                if matches {
+                    generated = rule.generated.unwrap_or(generated);
+                    vendored = rule.vendored.unwrap_or(vendored);
+                }
+            }
+            dir.push(component);
+        }
+
+        if generated {
+            Some(LinguistMark::Generated)
+        } else if vendored {
+            Some(LinguistMark::Vendored)
+        } else {
+            None
+        }
+    }
+
+    fn rules_in(&self, dir: &Path) -> Rc<Vec<Rule>> {
+        self.rules
+            .borrow_mut()
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let rules = fs::read(dir.join(ATTRIBUTES_FILENAME))
+                    .map(|bytes| parse_rules(&bytes))
+                    .unwrap_or_default();
+                Rc::new(rules)
+            })
+            .clone()
+    }
+}
+
+/// The linguist rules of a `.gitattributes` file; macros and malformed lines are passed over.
+fn parse_rules(bytes: &[u8]) -> Vec<Rule> {
+    gix::attrs::parse(bytes)
+        .filter_map(Result::ok)
+        .filter_map(|(kind, assignments, _)| {
+            let gix::attrs::parse::Kind::Pattern(pattern) = kind else {
+                return None;
+            };
+            let mut rule = Rule {
+                pattern,
+                generated: None,
+                vendored: None,
+            };
+            for assignment in assignments.filter_map(Result::ok) {
+                let value = match assignment.state {
+                    gix::attrs::StateRef::Set => Some(true),
+                    gix::attrs::StateRef::Unset => Some(false),
+                    gix::attrs::StateRef::Value(value) => {
+                        Some(value.as_bstr().to_str_lossy() != "false")
+                    }
+                    gix::attrs::StateRef::Unspecified => Some(false),
+                };
+                match assignment.name.as_str() {
+                    "linguist-generated" => rule.generated = value,
+                    "linguist-vendored" => rule.vendored = value,
+                    _ => {}
+                }
+            }
+            (rule.generated.is_some() || rule.vendored.is_some()).then_some(rule)
+        })
+        .collect()
+}
@@ -0,0 +1,373 @@
+use crate::remote::RepoLocation;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+/// Downloads in progress; anything left here is from an interrupted fetch.
+pub const STAGING_DIR: &str = ".staging";
+
+const ENTRY_FILENAME: &str = "entry.json";
+const REFS_FILENAME: &str = "refs.json";
+const FILES_DIR: &str = "files";
+const GIT_DIR: &str = "repo.git";
+
+/// A fetched revision: the repository's files (whole tree, not a subdirectory) and, for git
+/// fetches, the bare clone they came from.
+#[derive(Debug, Clone)]
+pub struct FetchedTree {
+    pub files: PathBuf,
+    pub commit: Option<String>,
+    pub git_dir: Option<PathBuf>,
+}
+
+/// What the cache remembers about a ref: which entry it last resolved to and the archive ETag
+/// to revalidate it with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedRef {
+    pub key: String,
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EntryInfo {
+    url: String,
+    commit: Option<String>,
+    size: u64,
+    last_used: u64,
+    /// Commits of history in the clone, 0 for all of it; `None` for entries without a clone.
+    #[serde(default)]
+    depth: Option<u32>,
+}
+
+/// Downloaded repositories under `<root>/<host>/<owner>/<repo>/<commit>/`, evicted least
+/// recently used first once they take more than `max_size`. Revisions whose commit the host
+/// does not report are stored under `ref-<ref>` and revalidated by ETag.
+pub struct RepoCache {
+    root: PathBuf,
+    max_size: u64,
+}
+
+impl RepoCache {
+    pub fn new(root: PathBuf, max_size: u64) -> Self {
+        Self { root, max_size }
+    }
+
+    /// A fresh directory to fetch into before the result is moved into the cache.
+    pub fn staging_dir(&self) -> Result<PathBuf> {
+        let dir = self.root.join(STAGING_DIR).join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// The cached files of `commit`, if there are any with at least `depth` commits of history
+    /// (see `insert`).
+    pub fn lookup_commit(
+        &self,
+        location: &RepoLocation,
+        commit: &str,
+        depth: Option<u32>,
+    ) -> Option<FetchedTree> {
+        self.open_entry(&self.repo_dir(location).join(commit), depth)
+    }
+
+    pub fn lookup_ref(
+        &self,
+        location: &RepoLocation,
+        reference: &str,
+    ) -> Option<(CachedRef, FetchedTree)> {
+        let repo_dir = self.repo_dir(location);
+        let cached = load_refs(&repo_dir).remove(reference)?;
+        let tree = self.open_entry(&repo_dir.join(&cached.key), None)?;
+        Some((cached, tree))
+    }
+
+    /// Moves `tree` into the cache as the current revision of `reference` and returns where it
+    /// now lives. `depth` is the history of a cloned tree, 0 for all of it. An existing entry for
+    /// the same commit is reused instead, unless its clone has less history.
+    pub fn insert(
+        &self,
+        location: &RepoLocation,
+        reference: &str,
+        tree: FetchedTree,
+        etag: Option<String>,
+        depth: Option<u32>,
+    ) -> Result<FetchedTree> {
+        let repo_dir = self.repo_dir(location);
+        let key = match &tree.commit {
+            Some(commit) => sanitize_component(commit),
+            None => format!("ref-{}", sanitize_component(reference)),
+        };
+        let entry_dir = repo_dir.join(&key);
+
+        let existing = self
+            .open_entry(&entry_dir, depth)
+            .filter(|_| tree.commit.is_some());
+        let stored = match existing {
+            Some(existing) => existing,
+            None => {
+                if entry_dir.exists() {
+                    fs::remove_dir_all(&entry_dir)?;
+                }
+                fs::create_dir_all(&entry_dir)?;
+                fs::rename(&tree.files, entry_dir.join(FILES_DIR))
+                    .context("Moving the download into the repository cache")?;
+                if let Some(git_dir) = &tree.git_dir {
+                    fs::rename(git_dir, entry_dir.join(GIT_DIR))?;
+                }
+
+                let info = EntryInfo {
+                    url: location.url.clone(),
+                    commit: tree.commit.clone(),
+                    size: dir_size(&entry_dir),
+                    last_used: now(),
+                    depth: tree.git_dir.as_ref().and(depth),
+                };
+                write_json(&entry_dir.join(ENTRY_FILENAME), &info)?;
+                self.open_entry(&entry_dir, None)
+                    .context("Repository cache entry disappeared")?
+            }
+        };
+
+        let mut refs = load_refs(&repo_dir);
+        refs.insert(reference.to_string(), CachedRef { key, etag });
+        write_json(&repo_dir.join(REFS_FILENAME), &refs)?;
+
+        if let Err(e) = self.evict(&entry_dir) {
+            eprintln!("Failed to trim the repository cache: {}", e);
+        }
+        Ok(stored)
+    }
+
+    /// Removes least recently used entries until the cache fits `max_size`, never `keep`.
+    fn evict(&self, keep: &Path) -> Result<()> {
+        let mut entries = Vec::new();
+        collect_entries(&self.root, &mut entries)?;
+
+        let mut total: u64 = entries.iter().map(|(_, info)| info.size).sum();
+        entries.sort_by_key(|(_, info)| info.last_used);
+
+        for (dir, info) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            if dir == keep {
+                continue;
+            }
+            fs::remove_dir_all(&dir)?;
+            total = total.saturating_sub(info.size);
+        }
+        Ok(())
+    }
+
+    /// The entry in `entry_dir`, if it is complete and its clone has at least `depth` commits.
+    fn open_entry(&self, entry_dir: &Path, depth: Option<u32>) -> Option<FetchedTree> {
+        let info_path = entry_dir.join(ENTRY_FILENAME);
+        let mut info: EntryInfo = read_json(&info_path)?;
+        let files = entry_dir.join(FILES_DIR);
+        if !files.is_dir() || !has_history(info.depth, depth) {
+            return None;
+        }
+
+        info.last_used = now();
+        let _ = write_json(&info_path, &info);
+
+        let git_dir = entry_dir.join(GIT_DIR);
+        Some(FetchedTree {
+            files,
+            commit: info.commit,
+            git_dir: git_dir.is_dir().then_some(git_dir),
+        })
+    }
+
+    fn repo_dir(&self, location: &RepoLocation) -> PathBuf {
+        let mut dir = self.root.clone();
+        if location.base_url.is_empty() {
+            dir.push("other");
+            dir.push(sanitize_component(&location.url));
+        } else {
+            dir.push(sanitize_component(location.host()));
+            for segment in location.repo_path.split('/').filter(|s| !s.is_empty()) {
+                dir.push(sanitize_component(segment));
+            }
+        }
+        dir
+    }
+}
+
+/// Mirrors `src` into `dst` with hard links, falling back to copies across file systems, so a
+/// parse can work on (and later delete) its own tree without touching the cache.
+pub fn link_tree(src: &Path, dst: &Path) -> Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else if file_type.is_file() && fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a clone of depth `have` holds the `wanted` history; 0 stands for all of it.
+fn has_history(have: Option<u32>, wanted: Option<u32>) -> bool {
+    match (have, wanted) {
+        (_, None) => true,
+        (Some(0), _) => true,
+        (Some(have), Some(wanted)) => wanted != 0 && have >= wanted,
+        (None, Some(_)) => false,
+    }
+}
+
+fn collect_entries(dir: &Path, entries: &mut Vec<(PathBuf, EntryInfo)>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() || path.file_name().is_some_and(|n| n == STAGING_DIR) {
+            continue;
+        }
+        match read_json::<EntryInfo>(&path.join(ENTRY_FILENAME)) {
+            Some(info) => entries.push((path, info)),
+            None => collect_entries(&path, entries)?,
+        }
+    }
+    Ok(())
+}
+
+fn load_refs(repo_dir: &Path) -> BTreeMap<String, CachedRef> {
+    read_json(&repo_dir.join(REFS_FILENAME)).unwrap_or_default()
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    let file = File::open(path).ok()?;
+    serde_json::from_reader(BufReader::new(file)).ok()
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), value)?;
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map(|m| m.len()).unwrap_or(0),
+        })
+        .sum()
+}
+
+fn sanitize_component(input: &str) -> String {
+    let sanitized: String = input
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(100)
+        .collect();
+
+    match sanitized.trim_matches('.') {
+        "" => "_".to_string(),
+        _ => sanitized,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::parse_repo_url;
+    use crate::test_support::{write_file, TempDir};
+
+    const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// A fetched tree with a (fake) clone, as if staged by a git fetch.
+    fn staged(cache: &RepoCache, readme: &str) -> FetchedTree {
+        let staging = cache.staging_dir().unwrap();
+        write_file(&staging, "files/README.md", readme);
+        write_file(&staging, "git/HEAD", "ref: refs/heads/main\n");
+        FetchedTree {
+            files: staging.join("files"),
+            commit: Some(SHA.to_string()),
+            git_dir: Some(staging.join("git")),
+        }
+    }
+
+    #[test]
+    fn replaces_a_shallower_clone_of_the_same_commit() {
+        let dir = TempDir::new();
+        let cache = RepoCache::new(dir.path().to_path_buf(), DEFAULT_CACHE_MAX_SIZE);
+        let location = parse_repo_url("https://github.com/o/r", None, None).unwrap();
+
+        let shallow = staged(&cache, "shallow");
+        cache
+            .insert(&location, "main", shallow, None, Some(1))
+            .unwrap();
+        assert!(cache.lookup_commit(&location, SHA, Some(1)).is_some());
+        assert!(cache.lookup_commit(&location, SHA, Some(50)).is_none());
+        assert!(cache.lookup_commit(&location, SHA, Some(0)).is_none());
+
+        let deep = staged(&cache, "deep");
+        let stored = cache
+            .insert(&location, "main", deep, None, Some(50))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(stored.files.join("README.md")).unwrap(),
+            "deep"
+        );
+        assert!(cache.lookup_commit(&location, SHA, Some(10)).is_some());
+
+        // A shallower fetch keeps the deeper entry.
+        let again = staged(&cache, "again");
+        let stored = cache
+            .insert(&location, "main", again, None, Some(1))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(stored.files.join("README.md")).unwrap(),
+            "deep"
+        );
+    }
+
+    #[test]
+    fn archives_have_no_history() {
+        let dir = TempDir::new();
+        let cache = RepoCache::new(dir.path().to_path_buf(), DEFAULT_CACHE_MAX_SIZE);
+        let location = parse_repo_url("https://github.com/o/r", None, None).unwrap();
+
+        let mut archive = staged(&cache, "archive");
+        archive.git_dir = None;
+        cache
+            .insert(&location, "main", archive, None, None)
+            .unwrap();
+
+        assert!(cache.lookup_commit(&location, SHA, None).is_some());
+        assert!(cache.lookup_commit(&location, SHA, Some(1)).is_none());
+    }
+}
@@ -1,11 +1,20 @@
+use crate::credentials;
+use crate::download::{self, DownloadHandle, DownloadOptions};
 use crate::error::CommandError;
-use crate::utils::{self, ParseMetadata, ParsedPath, PARSED_FILES_DIR};
+use crate::exclusions;
+use crate::remote::Provider;
+use crate::skip_report::SkipReport;
+use crate::tree_export::{self, TreeExportOptions, TreeFormat};
+use crate::utils::{
+    self, FetchMethod, ParseMetadata, ParseOptions, ParsedPath, SourceInput, PARSED_FILES_DIR,
+};
 use anyhow::Result;
 use chrono::{DateTime, Local};
 use futures::future::join_all;
 use std::fs;
 use std::path::PathBuf;
 use tokio::fs as tokio_fs;
+use uuid::Uuid;
 
 #[derive(serde::Serialize)]
 pub struct ParsedFileListItem {
@@ -28,21 +37,42 @@ pub struct ParsedFileListItem {
 pub async fn parse(
     paths: Vec<String>,
     remote_url: Option<String>,
+    options: Option<ParseOptions>,
     app: tauri::AppHandle,
 ) -> Result<ParseMetadata, CommandError> {
-    let result =
-        tauri::async_runtime::spawn_blocking(move || utils::parse_files(paths, app, remote_url))
-            .await
-            .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
-            .map_err(CommandError::from)?;
+    let options = options.unwrap_or_default();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        utils::parse_files(paths, app, remote_url, options)
+    })
+    .await
+    .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
+    .map_err(CommandError::from)?;
 
     Ok(result)
 }
 
 #[tauri::command]
-pub async fn parse_repository(url: String) -> Result<String, CommandError> {
+pub async fn parse_repository(
+    url: String,
+    r#ref: Option<String>,
+    method: Option<FetchMethod>,
+    provider: Option<Provider>,
+    options: Option<DownloadOptions>,
+    download_id: Option<String>,
+    app: tauri::AppHandle,
+) -> Result<String, CommandError> {
     let result = tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<String> {
-        let path = utils::download_github_repo(&url)?;
+        let download_id = download_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let handle = DownloadHandle::register(&download_id);
+        let path = utils::fetch_repository(
+            &url,
+            r#ref.as_deref(),
+            method.unwrap_or_default(),
+            provider,
+            &options.unwrap_or_default(),
+            &handle,
+            &app,
+        )?;
         Ok(path.to_string_lossy().to_string())
     })
     .await
@@ -52,6 +82,112 @@ pub async fn parse_repository(url: String) -> Result<String, CommandError> {
     Ok(result)
 }
 
+#[tauri::command]
+pub async fn parse_pull_request(
+    url: String,
+    include_neighbors: Option<bool>,
+    options: Option<ParseOptions>,
+    app: tauri::AppHandle,
+) -> Result<ParseMetadata, CommandError> {
+    let options = options.unwrap_or_default();
+    let include_neighbors = include_neighbors.unwrap_or(false);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        utils::parse_pull_request(&url, include_neighbors, options, app)
+    })
+    .await
+    .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
+    .map_err(CommandError::from)?;
+
+    Ok(result)
+}
+
+/// Fetches and parses several repositories and local paths into one output, each under its own
+/// top-level name.
+#[tauri::command]
+pub async fn parse_sources(
+    sources: Vec<SourceInput>,
+    options: Option<ParseOptions>,
+    download_options: Option<DownloadOptions>,
+    download_id: Option<String>,
+    app: tauri::AppHandle,
+) -> Result<ParseMetadata, CommandError> {
+    let options = options.unwrap_or_default();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let download_id = download_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let handle = DownloadHandle::register(&download_id);
+        utils::parse_sources(
+            sources,
+            options,
+            &download_options.unwrap_or_default(),
+            &handle,
+            app,
+        )
+    })
+    .await
+    .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
+    .map_err(CommandError::from)?;
+
+    Ok(result)
+}
+
+/// Stops the `parse_repository` or `parse_sources` call started with `download_id`; it then
+/// fails with "Download cancelled". Returns false when nothing with that id is running.
+#[tauri::command]
+pub fn cancel_download(download_id: String) -> bool {
+    download::cancel(&download_id)
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Access Tokens
+// /////////////////////////////////////////////////////////////////////////////
+
+#[tauri::command]
+pub async fn set_access_token(host: String, token: String) -> Result<(), CommandError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        credentials::set_token(&utils::get_app_dir()?, &host, &token)
+    })
+    .await
+    .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
+    .map_err(CommandError::from)
+}
+
+#[tauri::command]
+pub async fn remove_access_token(host: String) -> Result<(), CommandError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        credentials::remove_token(&utils::get_app_dir()?, &host)
+    })
+    .await
+    .map_err(|e| CommandError::from(anyhow::anyhow!("Thread join error: {}", e)))?
+    .map_err(CommandError::from)
+}
+
+/// Hosts with a stored token; the tokens themselves never leave the keyring.
+#[tauri::command]
+pub fn get_token_hosts() -> Result<Vec<String>, CommandError> {
+    Ok(credentials::list_hosts(&utils::get_app_dir()?)?)
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Exclusions
+// /////////////////////////////////////////////////////////////////////////////
+
+/// Patterns left out of previews and parses, the built-in defaults until edited.
+#[tauri::command]
+pub fn get_exclusions() -> Result<Vec<String>, CommandError> {
+    Ok(exclusions::list(&utils::get_app_dir()?)?)
+}
+
+#[tauri::command]
+pub fn set_exclusions(patterns: Vec<String>) -> Result<(), CommandError> {
+    Ok(exclusions::save(&utils::get_app_dir()?, &patterns)?)
+}
+
+/// Restores the built-in list and returns it.
+#[tauri::command]
+pub fn reset_exclusions() -> Result<Vec<String>, CommandError> {
+    Ok(exclusions::reset(&utils::get_app_dir()?)?)
+}
+
 // /////////////////////////////////////////////////////////////////////////////
 // File System Preview & Expansion
 // /////////////////////////////////////////////////////////////////////////////
@@ -91,23 +227,26 @@ pub async fn get_preview_tree(paths: Vec<String>) -> Result<Vec<ParsedPath>, Com
 }
 
 #[tauri::command]
-pub async fn expand_folder(path: String) -> Result<Vec<ParsedPath>, CommandError> {
+pub async fn expand_folder(
+    path: String,
+    root: Option<String>,
+) -> Result<Vec<ParsedPath>, CommandError> {
     let result = tauri::async_runtime::spawn_blocking(move || {
         let path_buf = PathBuf::from(path);
+        // Exclusions are matched below the selected path the folder was expanded from.
+        let root = root.map_or_else(|| path_buf.clone(), PathBuf::from);
 
         let mut children = Vec::new();
         if path_buf.is_dir() {
             if let Ok(entries) = fs::read_dir(&path_buf) {
                 for entry in entries.flatten() {
                     let child_path = entry.path();
-                    if child_path.is_symlink() {
+                    if !utils::is_valid_path(&child_path, &root) {
                         continue;
                     }
 
-                    if let Ok(_node) = utils::build_file_tree_shallow(&child_path, &path_buf) {
-                        if let Ok(node) = utils::create_shallow_node(&child_path, &path_buf) {
-                            children.push(node);
-                        }
+                    if let Ok(node) = utils::create_shallow_node(&child_path, &path_buf, &root) {
+                        children.push(node);
                     }
                 }
             }
@@ -224,6 +363,12 @@ pub fn get_file_metadata(dir_name: String) -> Result<ParseMetadata, CommandError
     Ok(utils::load_metadata(&parse_dir)?)
 }
 
+#[tauri::command]
+pub fn get_skipped_files(dir_name: String) -> Result<SkipReport, CommandError> {
+    let parse_dir = utils::get_parse_dir(&dir_name)?;
+    Ok(SkipReport::load(&parse_dir)?)
+}
+
 #[tauri::command]
 pub fn update_file(dir_name: String, content: String) -> Result<(), CommandError> {
     let parse_dir = utils::get_parse_dir(&dir_name)?;
@@ -275,6 +420,22 @@ pub fn get_parsed_preview_tree(dir_name: String) -> Result<Vec<ParsedPath>, Comm
     Ok(shallow_tree)
 }
 
+#[tauri::command]
+pub fn export_tree(
+    dir_name: String,
+    format: TreeFormat,
+    options: Option<TreeExportOptions>,
+) -> Result<String, CommandError> {
+    let parse_dir = utils::get_parse_dir(&dir_name)?;
+    let full_tree = utils::load_tree(&parse_dir)?;
+
+    Ok(tree_export::render_tree(
+        &full_tree,
+        format,
+        &options.unwrap_or_default(),
+    )?)
+}
+
 // /////////////////////////////////////////////////////////////////////////////
 // System Actions
 // /////////////////////////////////////////////////////////////////////////////
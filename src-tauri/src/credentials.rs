@@ -0,0 +1,131 @@
+use crate::remote::Provider;
+use anyhow::{Context, Result};
+use keyring::Entry;
+use reqwest::blocking::RequestBuilder;
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+/// Keyring service the tokens are stored under, one entry per host.
+const KEYRING_SERVICE: &str = "parser-ai";
+
+/// Hosts that have a token stored, since the keyring cannot be listed. Holds no secrets.
+pub const TOKEN_HOSTS_FILENAME: &str = "token-hosts.json";
+
+/// Stores `token` in the OS keyring for `host` (a host name or any URL on it).
+pub fn set_token(app_dir: &Path, host: &str, token: &str) -> Result<()> {
+    let host = normalize_host(host)?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(anyhow::anyhow!("Access token cannot be empty"));
+    }
+
+    entry(&host)?.set_password(token).with_context(|| {
+        format!(
+            "Failed to store the access token for {} in the keyring",
+            host
+        )
+    })?;
+
+    let mut hosts = load_hosts(app_dir)?;
+    hosts.insert(host);
+    save_hosts(app_dir, &hosts)
+}
+
+pub fn remove_token(app_dir: &Path, host: &str) -> Result<()> {
+    let host = normalize_host(host)?;
+
+    match entry(&host)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => {
+            return Err(anyhow::anyhow!(
+                "Failed to remove the access token for {} from the keyring: {}",
+                host,
+                e
+            ))
+        }
+    }
+
+    let mut hosts = load_hosts(app_dir)?;
+    hosts.remove(&host);
+    save_hosts(app_dir, &hosts)
+}
+
+pub fn list_hosts(app_dir: &Path) -> Result<Vec<String>> {
+    Ok(load_hosts(app_dir)?.into_iter().collect())
+}
+
+/// The token configured for `host`, if any. The keyring is only asked for hosts known to have
+/// one, so public downloads never trigger a keyring unlock prompt.
+pub fn token_for(app_dir: &Path, host: &str) -> Option<String> {
+    let host = normalize_host(host).ok()?;
+    if !load_hosts(app_dir).ok()?.contains(&host) {
+        return None;
+    }
+
+    match entry(&host).and_then(|e| Ok(e.get_password()?)) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            eprintln!("Could not read the access token for {}: {}", host, e);
+            None
+        }
+    }
+}
+
+/// Adds `token` to a request the way `provider` expects it. Bitbucket app passwords are given as
+/// `username:app_password` and sent with basic auth. Tokens only ever go in the `Authorization`
+/// header, which reqwest drops when a redirect leads to another host.
+pub fn authorize(request: RequestBuilder, provider: Provider, token: &str) -> RequestBuilder {
+    match provider {
+        // GitLab takes personal access tokens as bearer tokens as well as in `PRIVATE-TOKEN`.
+        Provider::GitLab => request.bearer_auth(token),
+        Provider::Gitea => request.header("Authorization", format!("token {}", token)),
+        Provider::Bitbucket => match token.split_once(':') {
+            Some((user, password)) => request.basic_auth(user, Some(password)),
+            None => request.bearer_auth(token),
+        },
+        Provider::GitHub | Provider::Archive | Provider::Git => request.bearer_auth(token),
+    }
+}
+
+/// `host[:port]` in lower case, accepting a bare host or a full URL.
+pub fn normalize_host(input: &str) -> Result<String> {
+    let trimmed = input.trim();
+    let without_scheme = trimmed.split_once("://").map_or(trimmed, |(_, rest)| rest);
+    let host = without_scheme
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("Invalid host: '{}'", input));
+    }
+    Ok(host)
+}
+
+fn entry(host: &str) -> Result<Entry> {
+    Entry::new(KEYRING_SERVICE, host).context("OS keyring is not available")
+}
+
+fn load_hosts(app_dir: &Path) -> Result<BTreeSet<String>> {
+    let path = app_dir.join(TOKEN_HOSTS_FILENAME);
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let file = File::open(&path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+fn save_hosts(app_dir: &Path, hosts: &BTreeSet<String>) -> Result<()> {
+    fs::create_dir_all(app_dir)?;
+    let file = File::create(app_dir.join(TOKEN_HOSTS_FILENAME))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), hosts)?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use crate::git;
+use crate::tree_export::format_size;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_MAX_ARCHIVE_SIZE: u64 = 1024 * 1024 * 1024;
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 60;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Minimum time between two progress callbacks; the last chunk is always reported.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+static ACTIVE_DOWNLOADS: LazyLock<Mutex<HashMap<String, Arc<AtomicBool>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Downloads announcing or reaching a larger size are aborted.
+    pub max_archive_size: u64,
+    pub connect_timeout_secs: u64,
+    /// How long a single read may stall before the download is given up.
+    pub read_timeout_secs: u64,
+    /// Reuse and fill the on-disk repository cache.
+    pub use_cache: bool,
+    /// Fetch only the requested subdirectory through the host's API where possible, instead
+    /// of the whole archive.
+    pub sparse_subdir: bool,
+    /// Files above this size are left out of sparse fetches and reported as too large, for
+    /// parses whose `skip` strategy would leave them out anyway. `None`, the default, fetches
+    /// every file, as the truncating strategies need them.
+    pub max_file_size: Option<u64>,
+    /// Commits fetched when cloning with git, 0 for the full history. A history section never
+    /// reaches further back; cached clones keep the depth they were fetched with.
+    pub clone_depth: u32,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            max_archive_size: DEFAULT_MAX_ARCHIVE_SIZE,
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            read_timeout_secs: DEFAULT_READ_TIMEOUT_SECS,
+            use_cache: true,
+            sparse_subdir: true,
+            max_file_size: None,
+            clone_depth: git::DEFAULT_CLONE_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub download_id: String,
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+/// Cancellation flag of a running download, registered under its id until dropped.
+pub struct DownloadHandle {
+    id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl DownloadHandle {
+    pub fn register(id: &str) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        ACTIVE_DOWNLOADS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id.to_string(), cancelled.clone());
+        Self {
+            id: id.to_string(),
+            cancelled,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn flag(&self) -> &AtomicBool {
+        &self.cancelled
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for DownloadHandle {
+    fn drop(&mut self) {
+        ACTIVE_DOWNLOADS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/// Asks the download registered under `id` to stop. Returns false when no such download runs.
+pub fn cancel(id: &str) -> bool {
+    match ACTIVE_DOWNLOADS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(id)
+    {
+        Some(flag) => {
+            flag.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn http_client(options: &DownloadOptions) -> Result<reqwest::blocking::Client> {
+    // The blocking client applies `timeout` to every read of a streamed body, not to the
+    // whole transfer, so it works as a stall timeout.
+    Ok(reqwest::blocking::Client::builder()
+        .user_agent("parser-ai-app")
+        .connect_timeout(Duration::from_secs(options.connect_timeout_secs))
+        .timeout(Duration::from_secs(options.read_timeout_secs))
+        .build()?)
+}
+
+/// Streams `reader` into `dest` in chunks, enforcing `max_size` and checking `handle` for
+/// cancellation between chunks. A partial file is removed on failure.
+pub fn save_to_file<R: Read>(
+    mut reader: R,
+    total: Option<u64>,
+    dest: &Path,
+    max_size: u64,
+    handle: &DownloadHandle,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<u64> {
+    if total.is_some_and(|t| t > max_size) {
+        return Err(too_large(max_size));
+    }
+
+    let result = (|| -> Result<u64> {
+        let mut writer = BufWriter::new(File::create(dest)?);
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut received = 0u64;
+        let mut last_report: Option<Instant> = None;
+
+        loop {
+            if handle.is_cancelled() {
+                return Err(anyhow::anyhow!("Download cancelled"));
+            }
+
+            let read = reader.read(&mut buffer)?;
+            if read > 0 {
+                received += read as u64;
+                if received > max_size {
+                    return Err(too_large(max_size));
+                }
+                writer.write_all(&buffer[..read])?;
+            }
+
+            let done = read == 0;
+            if done || last_report.is_none_or(|t| t.elapsed() >= PROGRESS_INTERVAL) {
+                on_progress(DownloadProgress {
+                    download_id: handle.id().to_string(),
+                    received,
+                    total,
+                });
+                last_report = Some(Instant::now());
+            }
+
+            if done {
+                writer.flush()?;
+                return Ok(received);
+            }
+        }
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    result
+}
+
+fn too_large(max_size: u64) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Archive is larger than the {} download limit",
+        format_size(max_size)
+    )
+}
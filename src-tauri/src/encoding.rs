@@ -0,0 +1,150 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::{Deserialize, Serialize};
+
+/// Share of NUL bytes on one side of the code units for BOM-less text to be treated as UTF-16.
+const UTF16_NUL_RATIO: f32 = 0.3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscodedFile {
+    pub relative_path: String,
+    pub encoding: String,
+    pub had_bom: bool,
+}
+
+pub struct DecodedText {
+    pub content: String,
+    pub encoding: &'static Encoding,
+    pub had_bom: bool,
+}
+
+/// Picks the encoding of `sample` (usually the start of a file). Returns the encoding and the
+/// length of its BOM, or 0 when there is none.
+pub fn detect_encoding(sample: &[u8]) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(sample) {
+        return (encoding, bom_len);
+    }
+
+    // ASCII in UTF-16 is also valid UTF-8 full of NULs, so check it first.
+    if let Some(encoding) = detect_utf16_without_bom(sample) {
+        return (encoding, 0);
+    }
+
+    if is_utf8_prefix(sample) {
+        return (UTF_8, 0);
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(sample, true);
+    (detector.guess(None, true), 0)
+}
+
+pub fn decode_bytes(bytes: &[u8]) -> DecodedText {
+    let (encoding, bom_len) = detect_encoding(bytes);
+    let (content, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+
+    DecodedText {
+        content: content.into_owned(),
+        encoding,
+        had_bom: bom_len > 0,
+    }
+}
+
+/// UTF-16 text without a BOM: mostly-ASCII content leaves every other byte NUL.
+pub fn detect_utf16_without_bom(sample: &[u8]) -> Option<&'static Encoding> {
+    if sample.len() < 4 {
+        return None;
+    }
+
+    let pairs = sample.len() / 2;
+    let even_nuls = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_nuls = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count();
+
+    let even_ratio = even_nuls as f32 / pairs as f32;
+    let odd_ratio = odd_nuls as f32 / pairs as f32;
+
+    if odd_ratio >= UTF16_NUL_RATIO && even_ratio < 0.05 {
+        Some(UTF_16LE)
+    } else if even_ratio >= UTF16_NUL_RATIO && odd_ratio < 0.05 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+pub fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// Valid UTF-8, allowing for a sample cut from a larger file in the middle of a character.
+fn is_utf8_prefix(sample: &[u8]) -> bool {
+    match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && sample.len() - e.valid_up_to() < 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn strips_byte_order_marks() {
+        let decoded = decode_bytes(b"\xEF\xBB\xBFhello");
+        assert_eq!(decoded.content, "hello");
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(decoded.had_bom);
+
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(utf16le("hello"));
+        let decoded = decode_bytes(&bytes);
+        assert_eq!(decoded.content, "hello");
+        assert_eq!(decoded.encoding, UTF_16LE);
+        assert!(decoded.had_bom);
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        let decoded = decode_bytes(&utf16le("fn main() {}\n"));
+        assert_eq!(decoded.content, "fn main() {}\n");
+        assert_eq!(decoded.encoding, UTF_16LE);
+        assert!(!decoded.had_bom);
+
+        let big_endian: Vec<u8> = "fn main() {}\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        assert_eq!(detect_encoding(&big_endian), (UTF_16BE, 0));
+    }
+
+    #[test]
+    fn keeps_utf8_cut_in_the_middle_of_a_character() {
+        let text = "naïve café".as_bytes();
+        assert_eq!(detect_encoding(&text[..text.len() - 1]), (UTF_8, 0));
+    }
+
+    #[test]
+    fn guesses_legacy_encodings() {
+        let french = "Le café était déjà fermé à côté de la forêt, où l'élève rêvait.\n";
+        let (bytes, _, _) = WINDOWS_1252.encode(french);
+        let decoded = decode_bytes(&bytes);
+        assert_eq!(decoded.encoding, WINDOWS_1252);
+        assert_eq!(decoded.content, french);
+
+        let japanese = "これは日本語のテキストです。文字コードはシフトJISです。\n";
+        let (bytes, _, _) = SHIFT_JIS.encode(japanese);
+        let decoded = decode_bytes(&bytes);
+        assert_eq!(decoded.encoding, SHIFT_JIS);
+        assert_eq!(decoded.content, japanese);
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use gix::bstr::BStr;
+use gix::glob::{pattern::Case, wildmatch, Pattern};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Component, Path},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+/// The user's exclusion list; missing until the defaults are first edited.
+pub const EXCLUSIONS_FILENAME: &str = "exclusions.json";
+
+/// Lockfiles, dependency directories, build output and minified or generated assets: large,
+/// rarely read and present in most repositories whether or not they are gitignored.
+pub const DEFAULT_EXCLUSIONS: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "pnpm-lock.yaml",
+    "yarn.lock",
+    "node_modules/",
+    "target/",
+    "__pycache__/",
+    ".venv/",
+    "dist/",
+    "*.min.js",
+    "*.min.css",
+    "*.js.map",
+    "*.css.map",
+];
+
+/// The saved list, parsed once and replaced whenever it is edited.
+static SAVED: LazyLock<Mutex<Option<Arc<Exclusions>>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Gitignore-style patterns for paths to leave out. A pattern without a slash matches a name at
+/// any depth, one with a leading or inner slash is anchored to the parsed root, a trailing slash
+/// matches directories only and `!` takes a path back in; later patterns win.
+#[derive(Default)]
+pub struct Exclusions {
+    patterns: Vec<Pattern>,
+}
+
+impl Exclusions {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                gix::glob::parse(pattern)
+                    .with_context(|| format!("Invalid exclusion pattern: '{}'", pattern))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Whether `path` is left out of a parse of `root`. Patterns only see the part of the path
+    /// below `root`, so directories above it never match and `root` itself is never excluded.
+    pub fn is_excluded(&self, path: &Path, root: &Path) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+
+        let relative: Vec<_> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        let relative = relative.join("/");
+        if relative.is_empty() {
+            return false;
+        }
+        let is_dir = path.is_dir();
+
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            // Patterns without a slash are matched against the name alone, the others against
+            // the whole path below the root.
+            let matches = pattern.matches_repo_relative_path(
+                BStr::new(relative.as_bytes()),
+                relative.rfind('/').map(|pos| pos + 1),
+                Some(is_dir),
+                Case::Sensitive,
+                wildmatch::Mode::NO_MATCH_SLASH_LITERAL,
+            );
+            if matches {
+                excluded = !pattern.is_negative();
+            }
+        }
+        excluded
+    }
+}
+
+/// The saved exclusion list, or the defaults when none was saved.
+pub fn list(app_dir: &Path) -> Result<Vec<String>> {
+    let path = app_dir.join(EXCLUSIONS_FILENAME);
+    if !path.exists() {
+        return Ok(DEFAULT_EXCLUSIONS.iter().map(|p| p.to_string()).collect());
+    }
+    let file = File::open(&path)?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Reading {}", path.display()))
+}
+
+/// Replaces the saved list after checking every pattern parses.
+pub fn save(app_dir: &Path, patterns: &[String]) -> Result<()> {
+    let exclusions = Exclusions::new(patterns)?;
+
+    fs::create_dir_all(app_dir)?;
+    let file = File::create(app_dir.join(EXCLUSIONS_FILENAME))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), patterns)?;
+
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(exclusions));
+    Ok(())
+}
+
+/// Goes back to `DEFAULT_EXCLUSIONS`.
+pub fn reset(app_dir: &Path) -> Result<Vec<String>> {
+    match fs::remove_file(app_dir.join(EXCLUSIONS_FILENAME)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    list(app_dir)
+}
+
+/// The saved list in parsed form. An unreadable list falls back to the defaults, so a broken
+/// file never stops parsing.
+pub fn saved(app_dir: &Path) -> Arc<Exclusions> {
+    let mut saved = SAVED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(exclusions) = saved.as_ref() {
+        return exclusions.clone();
+    }
+
+    let exclusions = list(app_dir)
+        .and_then(|patterns| Exclusions::new(&patterns))
+        .unwrap_or_else(|e| {
+            eprintln!("Using the default exclusions: {:#}", e);
+            default_exclusions()
+        });
+    let exclusions = Arc::new(exclusions);
+    *saved = Some(exclusions.clone());
+    exclusions
+}
+
+fn default_exclusions() -> Exclusions {
+    let defaults: Vec<String> = DEFAULT_EXCLUSIONS.iter().map(|p| p.to_string()).collect();
+    Exclusions::new(&defaults).expect("default exclusions are valid patterns")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_file, TempDir};
+
+    fn exclusions(patterns: &[&str]) -> Exclusions {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        Exclusions::new(&patterns).unwrap()
+    }
+
+    #[test]
+    fn matches_below_the_root_only() {
+        let dir = TempDir::new();
+        let root = dir.path().join("dist/app");
+        write_file(&root, "src/main.rs", "");
+        write_file(&root, "dist/bundle.js", "");
+        let exclusions = exclusions(&["dist/", "src/*.rs"]);
+
+        // The `dist` directory above the root is not part of the parse.
+        assert!(!exclusions.is_excluded(&root.join("src"), &root));
+        assert!(exclusions.is_excluded(&root.join("src/main.rs"), &root));
+        assert!(exclusions.is_excluded(&root.join("dist"), &root));
+        assert!(!exclusions.is_excluded(&dir.path().join("other.rs"), &root));
+    }
+
+    #[test]
+    fn anchors_patterns_with_a_slash() {
+        let dir = TempDir::new();
+        let root = dir.path();
+        write_file(root, "build/out.txt", "");
+        write_file(root, "src/build/gen.rs", "");
+        write_file(root, "docs/intro.md", "");
+        write_file(root, "src/docs/api.md", "");
+        let exclusions = exclusions(&["/build", "docs/*.md"]);
+
+        assert!(exclusions.is_excluded(&root.join("build"), root));
+        assert!(!exclusions.is_excluded(&root.join("src/build"), root));
+        assert!(exclusions.is_excluded(&root.join("docs/intro.md"), root));
+        assert!(!exclusions.is_excluded(&root.join("src/docs/api.md"), root));
+    }
+
+    #[test]
+    fn never_excludes_the_root() {
+        let dir = TempDir::new();
+        let root = dir.path().join("node_modules");
+        write_file(&root, "left-pad/index.js", "");
+        let exclusions = exclusions(&["node_modules/"]);
+
+        assert!(!exclusions.is_excluded(&root, &root));
+        assert!(!exclusions.is_excluded(&root.join("left-pad/index.js"), &root));
+    }
+
+    #[test]
+    fn later_negations_take_paths_back() {
+        let dir = TempDir::new();
+        write_file(dir.path(), "app.min.js", "");
+        write_file(dir.path(), "keep.min.js", "");
+        let exclusions = exclusions(&["*.min.js", "!keep.min.js"]);
+
+        assert!(exclusions.is_excluded(&dir.path().join("app.min.js"), dir.path()));
+        assert!(!exclusions.is_excluded(&dir.path().join("keep.min.js"), dir.path()));
+    }
+}
@@ -0,0 +1,971 @@
+use anyhow::{Context, Result};
+use gix::object::tree::EntryKind;
+use gix::remote::fetch::Shallow;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Read,
+    num::NonZeroU32,
+    path::{Component, Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+
+/// Commits fetched per clone; enough for a snapshot of the tip.
+pub const DEFAULT_CLONE_DEPTH: u32 = 1;
+
+pub struct ClonedRevision {
+    /// Files of the requested revision (and subdirectory), without any `.git` directory.
+    pub path: PathBuf,
+    /// Bare repository the files were exported from, kept for history lookups.
+    pub git_dir: PathBuf,
+    pub commit: String,
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Remote Cloning
+// /////////////////////////////////////////////////////////////////////////////
+
+/// Clones `url` into `<dest_root>/<name>.git` and exports the requested revision to
+/// `<dest_root>/<name>`. Works for any remote gix understands: `https://`, `ssh://`, `git://`,
+/// `file://` and plain local paths (bare or not).
+///
+/// `reference` may be a branch, a tag or a commit SHA; `None` means the remote `HEAD`. Branches
+/// and tags are fetched `depth` commits deep (0 for full history). A reference the remote does
+/// not advertise but that looks like a commit SHA is fetched again with the full history, as
+/// servers only hand out shallow packs for advertised refs. Setting `should_interrupt` aborts
+/// the fetch.
+pub fn clone_repository(
+    url: &str,
+    dest_root: &Path,
+    name: &str,
+    reference: Option<&str>,
+    subdir: Option<&str>,
+    depth: u32,
+    should_interrupt: &AtomicBool,
+) -> Result<ClonedRevision> {
+    let shallow = match NonZeroU32::new(depth) {
+        Some(depth) => Shallow::DepthAtRemote(depth),
+        None => Shallow::NoChange,
+    };
+
+    let git_dir = dest_root.join(format!("{}.git", name));
+    // gix reads a full SHA given as ref name as an object id, so those skip the ref lookup.
+    let full_sha = reference.is_some_and(|r| r.len() == 40 && looks_like_commit_sha(r));
+    let by_ref =
+        (!full_sha).then(|| fetch_bare(url, &git_dir, reference, shallow, should_interrupt));
+
+    // The clone's HEAD follows the requested branch or tag; a commit SHA is looked up directly.
+    let (repo, commit_id) = match by_ref {
+        Some(Ok(repo)) => {
+            let commit_id = repo
+                .head_id()
+                .context("Remote has no HEAD commit")?
+                .detach();
+            (repo, commit_id)
+        }
+        Some(Err(e)) if !reference.is_some_and(looks_like_commit_sha) => return Err(e),
+        _ => {
+            let sha = reference.unwrap_or_default();
+            if git_dir.exists() {
+                fs::remove_dir_all(&git_dir)?;
+            }
+            let repo = fetch_bare(url, &git_dir, None, Shallow::NoChange, should_interrupt)?;
+            let commit_id = repo
+                .rev_parse_single(sha)
+                .with_context(|| format!("Commit {} not found in {}", sha, url))?
+                .detach();
+            (repo, commit_id)
+        }
+    };
+
+    let path = dest_root.join(name);
+    export_tree(&repo, commit_id, subdir, &path)?;
+
+    Ok(ClonedRevision {
+        path,
+        git_dir,
+        commit: commit_id.to_string(),
+    })
+}
+
+/// Fetches `url` into a new bare repository at `git_dir`, following `reference` if given.
+fn fetch_bare(
+    url: &str,
+    git_dir: &Path,
+    reference: Option<&str>,
+    shallow: Shallow,
+    should_interrupt: &AtomicBool,
+) -> Result<gix::Repository> {
+    fs::create_dir_all(git_dir)?;
+    let (repo, _) = gix::prepare_clone_bare(url, git_dir)
+        .with_context(|| format!("Invalid git remote: {}", url))?
+        .with_shallow(shallow)
+        .with_ref_name(reference)
+        .with_context(|| format!("Invalid ref name: {}", reference.unwrap_or_default()))?
+        .fetch_only(gix::progress::Discard, should_interrupt)
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    Ok(repo)
+}
+
+/// Writes the files of `commit_id` (optionally only `subdir`) to `dest`. Symlinks and submodules
+/// are left out, matching what a local parse skips.
+fn export_tree(
+    repo: &gix::Repository,
+    commit_id: gix::ObjectId,
+    subdir: Option<&str>,
+    dest: &Path,
+) -> Result<()> {
+    let mut tree = repo.find_commit(commit_id)?.tree()?;
+
+    if let Some(subdir) = subdir.filter(|s| !s.is_empty()) {
+        let entry = tree
+            .peel_to_entry_by_path(subdir)?
+            .with_context(|| format!("Path '{}' not found at {}", subdir, commit_id))?;
+        tree = entry
+            .object()?
+            .try_into_tree()
+            .with_context(|| format!("'{}' is not a directory", subdir))?;
+    }
+
+    let mut recorder = gix::traverse::tree::Recorder::default();
+    tree.traverse().breadthfirst(&mut recorder)?;
+
+    fs::create_dir_all(dest)?;
+    for entry in recorder.records {
+        let relative = gix::path::from_bstring(entry.filepath);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            continue;
+        }
+
+        let target = dest.join(&relative);
+        match entry.mode.kind() {
+            EntryKind::Tree => fs::create_dir_all(&target)?,
+            EntryKind::Blob | EntryKind::BlobExecutable => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let blob = repo.find_object(entry.oid)?;
+                fs::write(&target, &blob.data)?;
+            }
+            EntryKind::Link | EntryKind::Commit => {}
+        }
+    }
+
+    Ok(())
+}
+
+pub fn looks_like_commit_sha(reference: &str) -> bool {
+    (7..=40).contains(&reference.len()) && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Directory name for a clone, taken from the last path segment of the remote URL.
+pub fn repo_name_from_url(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+
+    if name.is_empty() {
+        "repo".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Local Working Trees
+// /////////////////////////////////////////////////////////////////////////////
+
+/// Where a local parse stood in git: branch, `HEAD` commit and whether tracked files had
+/// uncommitted changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorktreeState {
+    pub root: String,
+    /// `None` on a detached `HEAD`.
+    pub branch: Option<String>,
+    /// `None` before the first commit.
+    pub head_commit: Option<String>,
+    /// Untracked files do not make a working tree dirty.
+    pub dirty: bool,
+}
+
+/// Some files of a working tree, as paths relative to its (canonical) root, plus the
+/// directories leading to them.
+pub struct WorktreeFiles {
+    root: PathBuf,
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl WorktreeFiles {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            files: HashSet::new(),
+            dirs: HashSet::new(),
+        }
+    }
+
+    fn insert(&mut self, relative: PathBuf) {
+        let mut parent = relative.parent();
+        while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+            if !self.dirs.insert(dir.to_path_buf()) {
+                break;
+            }
+            parent = dir.parent();
+        }
+        self.files.insert(relative);
+    }
+
+    /// `path` relative to the working tree root, or `None` when it lies outside.
+    pub fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let canonical = fs::canonicalize(path).ok()?;
+        canonical
+            .strip_prefix(&self.root)
+            .ok()
+            .map(Path::to_path_buf)
+    }
+
+    /// Whether `path` is one of the files (or, for a directory, leads to one). `None` when
+    /// `path` is outside this working tree.
+    pub fn contains(&self, path: &Path) -> Option<bool> {
+        let relative = self.relative(path)?;
+        if relative.as_os_str().is_empty() {
+            return Some(true);
+        }
+        Some(self.files.contains(&relative) || self.dirs.contains(&relative))
+    }
+}
+
+/// The working tree `path` (a file or directory) belongs to; bare repositories and paths
+/// outside any repository give `None`.
+pub fn discover_worktree(path: &Path) -> Option<gix::Repository> {
+    let dir = if path.is_file() { path.parent()? } else { path };
+    gix::discover(dir)
+        .ok()
+        .filter(|repo| repo.workdir().is_some())
+}
+
+pub fn worktree_state(repo: &gix::Repository) -> Result<WorktreeState> {
+    let root = repo.workdir().context("Repository has no working tree")?;
+    let branch = repo.head_name()?.map(|name| name.shorten().to_string());
+    let head_commit = repo.head_id().ok().map(|id| id.to_string());
+    let dirty = match head_commit {
+        Some(_) => repo.is_dirty()?,
+        // Nothing committed yet: anything staged is a change.
+        None => !repo.index_or_empty()?.entries().is_empty(),
+    };
+
+    Ok(WorktreeState {
+        root: root.to_string_lossy().to_string(),
+        branch,
+        head_commit,
+        dirty,
+    })
+}
+
+/// Regular files of the index; symlinks and submodules are left out like everywhere else.
+pub fn tracked_files(repo: &gix::Repository) -> Result<WorktreeFiles> {
+    let mut tracked = WorktreeFiles::new(canonical_workdir(repo)?);
+    let index = repo.index_or_empty()?;
+    for path in index_paths(&index) {
+        tracked.insert(path);
+    }
+    Ok(tracked)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    /// Moved without content changes; a moved and edited file counts as added.
+    Renamed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedFile {
+    /// Relative to the working tree root.
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Path in the base revision of a renamed file.
+    pub from: Option<String>,
+    /// Unified diff against the base revision, when requested.
+    #[serde(skip)]
+    pub diff: Option<String>,
+}
+
+/// Tracked files of a working tree that differ from a base revision, including uncommitted
+/// changes. Deleted files have nothing to parse and are not listed.
+pub struct Changes {
+    pub base_commit: String,
+    pub files: Vec<ChangedFile>,
+    pub paths: WorktreeFiles,
+    /// Index into `files` by `ChangedFile::path`.
+    by_path: HashMap<String, usize>,
+}
+
+impl Changes {
+    pub fn get(&self, path: &Path) -> Option<&ChangedFile> {
+        let relative = unix_path(&self.paths.relative(path)?);
+        self.by_path.get(&relative).map(|&index| &self.files[index])
+    }
+}
+
+/// Compares the tracked files on disk with `base_ref` (a branch, tag or commit). With
+/// `diff_size_limit`, files up to that size on both sides get a unified diff.
+pub fn changes_since(
+    repo: &gix::Repository,
+    base_ref: &str,
+    diff_size_limit: Option<u64>,
+) -> Result<Changes> {
+    let root = canonical_workdir(repo)?;
+    let base_commit = repo
+        .rev_parse_single(base_ref)
+        .with_context(|| format!("Unknown git ref '{}'", base_ref))?
+        .object()?
+        .peel_to_commit()
+        .with_context(|| format!("'{}' does not name a commit", base_ref))?;
+
+    let base = commit_blobs(&base_commit)?;
+
+    let (mut pipeline, index) = repo.filter_pipeline(None)?;
+    let tracked = index_paths(&index);
+    let tracked_set: HashSet<&PathBuf> = tracked.iter().collect();
+    // Base files no longer tracked, by content, to recognise renames.
+    let removed: HashMap<gix::ObjectId, &PathBuf> = base
+        .iter()
+        .filter(|(path, _)| !tracked_set.contains(path))
+        .map(|(path, oid)| (*oid, path))
+        .collect();
+
+    let mut paths = WorktreeFiles::new(root.clone());
+    let mut files = Vec::new();
+    for relative in tracked {
+        // Deleted from the working tree but not from the index.
+        let Ok(bytes) = fs::read(root.join(&relative)) else {
+            continue;
+        };
+        let bytes = to_git(&mut pipeline, &index, &relative, bytes)?;
+        let oid = gix::objs::compute_hash(repo.object_hash(), gix::objs::Kind::Blob, &bytes)?;
+
+        let (kind, from) = match base.get(&relative) {
+            Some(base_oid) if *base_oid == oid => continue,
+            Some(_) => (ChangeKind::Modified, None),
+            None => match removed.get(&oid) {
+                Some(from) => (ChangeKind::Renamed, Some((*from).clone())),
+                None => (ChangeKind::Added, None),
+            },
+        };
+
+        let path = unix_path(&relative);
+        let diff = match (diff_size_limit, kind) {
+            (Some(limit), ChangeKind::Modified | ChangeKind::Added)
+                if bytes.len() as u64 <= limit =>
+            {
+                let before = match base.get(&relative) {
+                    Some(base_oid) => repo.find_object(*base_oid)?.detach().data,
+                    None => Vec::new(),
+                };
+                let old_name = match kind {
+                    ChangeKind::Added => "/dev/null".to_string(),
+                    _ => format!("a/{}", path),
+                };
+                (before.len() as u64 <= limit)
+                    .then(|| unified_diff(&before, &bytes, &old_name, &format!("b/{}", path)))
+                    .flatten()
+            }
+            _ => None,
+        };
+
+        files.push(ChangedFile {
+            path,
+            kind,
+            from: from.map(|from| unix_path(&from)),
+            diff,
+        });
+        paths.insert(relative);
+    }
+
+    let by_path = files
+        .iter()
+        .enumerate()
+        .map(|(index, file)| (file.path.clone(), index))
+        .collect();
+    Ok(Changes {
+        base_commit: base_commit.id.to_string(),
+        files,
+        paths,
+        by_path,
+    })
+}
+
+/// `git diff HEAD` of the working tree: staged and unstaged changes to tracked files, with
+/// files above `size_limit` or not in UTF-8 named but not shown. Files removed from the index
+/// show as deleted, even when still on disk. Only files for which `include`
+/// (given the path in the working tree, which may no longer exist) is true are compared. Empty
+/// when nothing changed.
+pub fn uncommitted_diff(
+    repo: &gix::Repository,
+    size_limit: u64,
+    mut include: impl FnMut(&Path) -> bool,
+) -> Result<String> {
+    let root = canonical_workdir(repo)?;
+    let head = if repo.head()?.is_unborn() {
+        HashMap::new()
+    } else {
+        commit_blobs(&repo.head_commit()?)?
+    };
+    let (mut pipeline, index) = repo.filter_pipeline(None)?;
+
+    let mut paths: Vec<PathBuf> = index_paths(&index);
+    let tracked: HashSet<PathBuf> = paths.iter().cloned().collect();
+    paths.extend(head.keys().filter(|path| !tracked.contains(*path)).cloned());
+    paths.sort();
+
+    let mut output = String::new();
+    for relative in paths {
+        if !include(&root.join(&relative)) {
+            continue;
+        }
+        let after = match fs::read(root.join(&relative)) {
+            Ok(bytes) if tracked.contains(&relative) => {
+                Some(to_git(&mut pipeline, &index, &relative, bytes)?)
+            }
+            _ => None,
+        };
+        let before_oid = head.get(&relative);
+        if let (Some(before_oid), Some(after)) = (before_oid, &after) {
+            let oid = gix::objs::compute_hash(repo.object_hash(), gix::objs::Kind::Blob, after)?;
+            if *before_oid == oid {
+                continue;
+            }
+        }
+        if before_oid.is_none() && after.is_none() {
+            continue;
+        }
+
+        let path = unix_path(&relative);
+        let old_name = match before_oid {
+            Some(_) => format!("a/{}", path),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match after {
+            Some(_) => format!("b/{}", path),
+            None => "/dev/null".to_string(),
+        };
+        let before = match before_oid {
+            Some(oid) => repo.find_object(*oid)?.detach().data,
+            None => Vec::new(),
+        };
+        let after = after.unwrap_or_default();
+
+        let too_large = before.len().max(after.len()) as u64 > size_limit;
+        let diff = (!too_large)
+            .then(|| unified_diff(&before, &after, &old_name, &new_name))
+            .flatten();
+        match diff {
+            Some(diff) => output.push_str(&diff),
+            None if too_large => output.push_str(&format!(
+                "--- {}\n+++ {}\n(diff omitted: larger than the file size limit)\n",
+                old_name, new_name
+            )),
+            None => output.push_str(&format!(
+                "Binary files {} and {} differ\n",
+                old_name, new_name
+            )),
+        }
+    }
+    Ok(output)
+}
+
+/// A working tree file's `bytes` as git stores them, after the `.gitattributes` and config
+/// conversions (line endings, `ident`, encodings, filter drivers), so they hash and diff like
+/// committed blobs.
+fn to_git(
+    pipeline: &mut gix::filter::Pipeline,
+    index: &gix::index::State,
+    relative: &Path,
+    bytes: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let mut converted = Vec::new();
+    pipeline
+        .convert_to_git(bytes.as_slice(), relative, index)?
+        .read_to_end(&mut converted)?;
+    Ok(converted)
+}
+
+/// Regular files of a commit's tree by path relative to the repository root.
+fn commit_blobs(commit: &gix::Commit) -> Result<HashMap<PathBuf, gix::ObjectId>> {
+    let mut recorder = gix::traverse::tree::Recorder::default();
+    commit.tree()?.traverse().breadthfirst(&mut recorder)?;
+    Ok(recorder
+        .records
+        .into_iter()
+        .filter(|entry| {
+            matches!(
+                entry.mode.kind(),
+                EntryKind::Blob | EntryKind::BlobExecutable
+            )
+        })
+        .map(|entry| (gix::path::from_bstring(entry.filepath), entry.oid))
+        .collect())
+}
+
+fn unix_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// `git diff`-style text for one file, `old_name` and `new_name` being the `a/`, `b/` or
+/// `/dev/null` names of its two sides; `None` for binary content or text that is not UTF-8.
+fn unified_diff(before: &[u8], after: &[u8], old_name: &str, new_name: &str) -> Option<String> {
+    // git's own binary check: a NUL byte near the start.
+    if [before, after]
+        .iter()
+        .any(|data| data[..data.len().min(8000)].contains(&0))
+    {
+        return None;
+    }
+
+    use gix::diff::blob::{
+        intern::InternedInput,
+        sources::byte_lines_with_terminator,
+        unified_diff::{ConsumeBinaryHunk, ContextSize},
+        Algorithm, UnifiedDiff,
+    };
+
+    let input = InternedInput::new(
+        byte_lines_with_terminator(before),
+        byte_lines_with_terminator(after),
+    );
+    let hunks = gix::diff::blob::diff(
+        Algorithm::Histogram,
+        &input,
+        UnifiedDiff::new(
+            &input,
+            ConsumeBinaryHunk::new(String::new(), "\n"),
+            ContextSize::symmetrical(3),
+        ),
+    )
+    .ok()?;
+
+    Some(format!("--- {}\n+++ {}\n{}", old_name, new_name, hunks))
+}
+
+fn index_paths(index: &gix::index::State) -> Vec<PathBuf> {
+    index
+        .entries()
+        .iter()
+        .filter(|entry| {
+            !entry.mode.is_submodule()
+                && !entry.mode.is_sparse()
+                && entry.mode != gix::index::entry::Mode::SYMLINK
+        })
+        .map(|entry| gix::path::from_bstr(entry.path(index)).into_owned())
+        .collect()
+}
+
+fn canonical_workdir(repo: &gix::Repository) -> Result<PathBuf> {
+    let root = repo.workdir().context("Repository has no working tree")?;
+    fs::canonicalize(root).with_context(|| format!("Resolving working tree {}", root.display()))
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// History
+// /////////////////////////////////////////////////////////////////////////////
+
+/// Commits looked at when searching for the last change of each file; older changes are
+/// not reported.
+const MAX_HISTORY_WALK: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitSummary {
+    pub id: String,
+    pub author: String,
+    /// Author time in seconds since the Unix epoch.
+    pub time: i64,
+    /// First line of the commit message.
+    pub summary: String,
+}
+
+pub struct History {
+    /// Newest first.
+    pub commits: Vec<CommitSummary>,
+    /// The commit that last changed each file, by path relative to the repository root.
+    pub last_changes: HashMap<PathBuf, CommitSummary>,
+}
+
+/// The bare clone kept next to a fetched repository.
+pub fn open_repository(git_dir: &Path) -> Result<gix::Repository> {
+    gix::open(git_dir).with_context(|| format!("Opening git repository {}", git_dir.display()))
+}
+
+/// `path` relative to the root of `repo`'s working tree, or `None` when it lies outside.
+pub fn worktree_relative(repo: &gix::Repository, path: &Path) -> Option<PathBuf> {
+    let root = canonical_workdir(repo).ok()?;
+    let canonical = fs::canonicalize(path).ok()?;
+    canonical.strip_prefix(root).ok().map(Path::to_path_buf)
+}
+
+/// The last `count` commits reachable from `tip` and the last change to each of `paths`.
+/// Shallow clones only know the commits they fetched; files not changed within them are left
+/// out of `last_changes`.
+pub fn history(
+    repo: &gix::Repository,
+    tip: &str,
+    count: usize,
+    paths: &[PathBuf],
+) -> Result<History> {
+    use gix::object::tree::diff::ChangeDetached;
+    use gix::revision::walk::Sorting;
+    use gix::traverse::commit::simple::CommitTimeOrder;
+
+    let tip = repo
+        .rev_parse_single(tip)
+        .with_context(|| format!("Unknown git revision '{}'", tip))?
+        .object()?
+        .peel_to_commit()?
+        .id;
+
+    let commits = repo
+        .rev_walk([tip])
+        .sorting(Sorting::ByCommitTime(CommitTimeOrder::NewestFirst))
+        .all()?
+        .take(count)
+        .map(|info| commit_summary(&info?.object()?))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Follow first parents like `git log --first-parent`: a file's last change is the first
+    // commit whose tree differs from its parent's at that path.
+    let mut remaining: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    let mut last_changes = HashMap::new();
+    let walk = repo.rev_walk([tip]).first_parent_only().all()?;
+    for info in walk.take(MAX_HISTORY_WALK) {
+        if remaining.is_empty() {
+            break;
+        }
+        let info = info?;
+        let commit = info.object()?;
+        let tree = commit.tree()?;
+
+        let changed: Vec<&Path> = match info.parent_ids().next() {
+            // A root commit added everything it has.
+            None => remaining
+                .iter()
+                .copied()
+                .filter(|path| matches!(tree.lookup_entry_by_path(path), Ok(Some(_))))
+                .collect(),
+            Some(parent_id) => {
+                // A shallow clone lacks the parents of its oldest commits, so what changed
+                // there is unknown; the files left are not reported rather than misdated.
+                let Some(parent) = repo.try_find_object(parent_id)? else {
+                    break;
+                };
+                let parent_tree = parent.peel_to_commit()?.tree()?;
+                if parent_tree.id == tree.id {
+                    continue;
+                }
+                repo.diff_tree_to_tree(&parent_tree, &tree, gix::diff::Options::default())?
+                    .iter()
+                    .filter(|change| !matches!(change, ChangeDetached::Deletion { .. }))
+                    .filter_map(|change| {
+                        let location = gix::path::from_bstr(change.location());
+                        remaining.get(location.as_ref()).copied()
+                    })
+                    .collect()
+            }
+        };
+
+        if !changed.is_empty() {
+            let summary = commit_summary(&commit)?;
+            for path in changed {
+                remaining.remove(path);
+                last_changes.insert(path.to_path_buf(), summary.clone());
+            }
+        }
+    }
+
+    Ok(History {
+        commits,
+        last_changes,
+    })
+}
+
+fn commit_summary(commit: &gix::Commit) -> Result<CommitSummary> {
+    let author = commit.author()?;
+    Ok(CommitSummary {
+        id: commit.id.to_string(),
+        author: author.name.to_string(),
+        time: author.seconds(),
+        summary: commit.message()?.summary().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, write_file, TempDir};
+
+    /// A repository with three commits on `main`, the first adding `README.md` and each
+    /// changing `src/lib.rs`, and one on `feature`.
+    fn source_repo(dir: &Path) -> PathBuf {
+        let repo = dir.join("source");
+        fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        write_file(&repo, "README.md", "readme");
+        for (i, content) in ["one", "two", "three"].iter().enumerate() {
+            write_file(&repo, "src/lib.rs", content);
+            git(&repo, &["add", "-A"]);
+            git(&repo, &["commit", "-qm", &format!("commit {}", i + 1)]);
+        }
+        git(&repo, &["checkout", "-qb", "feature"]);
+        write_file(&repo, "src/feature.rs", "feature");
+        git(&repo, &["add", "-A"]);
+        git(&repo, &["commit", "-qm", "feature"]);
+        git(&repo, &["checkout", "-q", "main"]);
+        repo
+    }
+
+    fn commit_count(git_dir: &Path) -> usize {
+        let repo = gix::open(git_dir).unwrap();
+        let head = repo.head_id().unwrap();
+        repo.rev_walk([head]).all().unwrap().count()
+    }
+
+    #[test]
+    fn clones_branch_shallow() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+        let url = format!("file://{}", source.display());
+
+        let cloned = clone_repository(
+            &url,
+            &dir.path().join("out"),
+            "repo",
+            Some("feature"),
+            None,
+            1,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(cloned.commit, git(&source, &["rev-parse", "feature"]));
+        assert_eq!(
+            fs::read_to_string(cloned.path.join("src/feature.rs")).unwrap(),
+            "feature"
+        );
+        assert!(!cloned.path.join(".git").exists());
+        assert_eq!(commit_count(&cloned.git_dir), 1);
+    }
+
+    #[test]
+    fn clones_full_history_and_subdir() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+
+        let cloned = clone_repository(
+            source.to_str().unwrap(),
+            &dir.path().join("out"),
+            "repo",
+            None,
+            Some("src"),
+            0,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(cloned.commit, git(&source, &["rev-parse", "main"]));
+        assert_eq!(
+            fs::read_to_string(cloned.path.join("lib.rs")).unwrap(),
+            "three"
+        );
+        assert!(!cloned.path.join("feature.rs").exists());
+        assert_eq!(commit_count(&cloned.git_dir), 3);
+    }
+
+    #[test]
+    fn checks_out_commit_sha() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+        let first = git(&source, &["rev-list", "--max-parents=0", "main"]);
+
+        let cloned = clone_repository(
+            source.to_str().unwrap(),
+            &dir.path().join("out"),
+            "repo",
+            Some(&first),
+            None,
+            1,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(cloned.commit, first);
+        assert_eq!(
+            fs::read_to_string(cloned.path.join("src/lib.rs")).unwrap(),
+            "one"
+        );
+        // An abbreviated SHA is no ref the remote knows, so it is looked up after a full fetch.
+        let short = clone_repository(
+            source.to_str().unwrap(),
+            &dir.path().join("short"),
+            "repo",
+            Some(&first[..7]),
+            None,
+            1,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(short.commit, first);
+    }
+
+    #[test]
+    fn treats_hex_named_branches_as_branches() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+        git(&source, &["branch", "cafe123", "feature"]);
+
+        let cloned = clone_repository(
+            &format!("file://{}", source.display()),
+            &dir.path().join("out"),
+            "repo",
+            Some("cafe123"),
+            None,
+            1,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+
+        assert_eq!(cloned.commit, git(&source, &["rev-parse", "feature"]));
+        assert_eq!(commit_count(&cloned.git_dir), 1);
+    }
+
+    #[test]
+    fn interrupted_clone_fails() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+
+        let result = clone_repository(
+            source.to_str().unwrap(),
+            &dir.path().join("out"),
+            "repo",
+            None,
+            None,
+            1,
+            &AtomicBool::new(true),
+        );
+
+        assert!(result.is_err());
+        assert!(!dir.path().join("out/repo").exists());
+    }
+
+    #[test]
+    fn compares_files_after_line_ending_conversion() {
+        let dir = TempDir::new();
+        let root = dir.path();
+        git(root, &["init", "-q"]);
+        write_file(root, ".gitattributes", "*.txt text eol=crlf\n");
+        write_file(root, "same.txt", "a\nb\n");
+        write_file(root, "edited.txt", "a\nb\n");
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-qm", "initial"]);
+
+        // Checked out with CRLF, as the attribute asks; only `edited.txt` really changed.
+        write_file(root, "same.txt", "a\r\nb\r\n");
+        write_file(root, "edited.txt", "a\r\nc\r\n");
+        let repo = gix::open(root).unwrap();
+
+        let changes = changes_since(&repo, "HEAD", Some(1024)).unwrap();
+        let paths: Vec<_> = changes
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(paths, ["edited.txt"]);
+        let changed = changes.get(&root.join("edited.txt")).unwrap();
+        assert!(!changed.diff.as_deref().unwrap().contains('\r'));
+        assert!(changes.get(&root.join("same.txt")).is_none());
+
+        let diff = uncommitted_diff(&repo, 1024, |_| true).unwrap();
+        assert!(diff.contains("b/edited.txt"), "{}", diff);
+        assert!(!diff.contains("same.txt"), "{}", diff);
+        assert!(!diff.contains('\r'), "{}", diff);
+
+        let filtered = uncommitted_diff(&repo, 1024, |path| !path.ends_with("edited.txt")).unwrap();
+        assert!(filtered.is_empty(), "{}", filtered);
+    }
+
+    #[test]
+    fn diffs_staged_and_unstaged_changes() {
+        let dir = TempDir::new();
+        let root = dir.path();
+        git(root, &["init", "-q"]);
+        write_file(root, "kept.txt", "kept\n");
+        write_file(root, "untracked.txt", "old\n");
+        write_file(root, "staged.txt", "old\n");
+        write_file(root, "edited.txt", "old\n");
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-qm", "initial"]);
+
+        git(root, &["rm", "-q", "--cached", "untracked.txt"]);
+        write_file(root, "staged.txt", "new\n");
+        git(root, &["add", "staged.txt"]);
+        write_file(root, "edited.txt", "new\n");
+        let repo = gix::open(root).unwrap();
+
+        let diff = uncommitted_diff(&repo, 1024, |_| true).unwrap();
+        assert!(
+            diff.contains("--- a/untracked.txt\n+++ /dev/null\n"),
+            "{}",
+            diff
+        );
+        assert!(diff.contains("+++ b/staged.txt"), "{}", diff);
+        assert!(diff.contains("+++ b/edited.txt"), "{}", diff);
+        assert!(!diff.contains("kept.txt"), "{}", diff);
+    }
+
+    #[test]
+    fn last_changes_stop_at_the_shallow_boundary() {
+        let dir = TempDir::new();
+        let source = source_repo(dir.path());
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "empty"]);
+        let paths = [PathBuf::from("src/lib.rs"), PathBuf::from("README.md")];
+        let summary_of = |history: &History, path: &str| {
+            history
+                .last_changes
+                .get(Path::new(path))
+                .map(|commit| commit.summary.clone())
+        };
+
+        let full = history(&gix::open(&source).unwrap(), "HEAD", 10, &paths).unwrap();
+        assert_eq!(full.commits.len(), 4);
+        assert_eq!(summary_of(&full, "src/lib.rs").as_deref(), Some("commit 3"));
+        assert_eq!(summary_of(&full, "README.md").as_deref(), Some("commit 1"));
+
+        // Three commits deep, the parent of `commit 2` is missing: `README.md` is unknown
+        // rather than put on `commit 2`.
+        let cloned = clone_repository(
+            &format!("file://{}", source.display()),
+            &dir.path().join("out"),
+            "repo",
+            None,
+            None,
+            3,
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        let shallow = history(&gix::open(&cloned.git_dir).unwrap(), "HEAD", 10, &paths).unwrap();
+        assert_eq!(shallow.commits.len(), 3);
+        assert_eq!(
+            summary_of(&shallow, "src/lib.rs").as_deref(),
+            Some("commit 3")
+        );
+        assert_eq!(summary_of(&shallow, "README.md"), None);
+    }
+}
@@ -1,12 +1,30 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+pub mod archive;
+pub mod attributes;
+pub mod cache;
 pub mod commands;
+pub mod credentials;
+pub mod download;
+pub mod encoding;
 pub mod error;
+pub mod exclusions;
+pub mod git;
+pub mod notebook;
+pub mod pull_request;
+pub mod remote;
+pub mod repo_info;
+pub mod skip_report;
+pub mod sparse;
+pub mod stats;
+#[cfg(test)]
+mod test_support;
+pub mod tree_export;
+pub mod truncate;
 pub mod utils;
 
 #[cfg(target_os = "windows")]
 use tauri::Manager;
 
-
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     if let Err(e) = utils::init_app_structure() {
@@ -36,9 +54,13 @@ pub fn run() {
             commands::get_parsed_preview_tree,
             commands::parse,
             commands::parse_repository,
+            commands::parse_pull_request,
+            commands::parse_sources,
+            commands::cancel_download,
             commands::get_files,
             commands::get_file_content,
             commands::get_file_metadata,
+            commands::get_skipped_files,
             commands::update_file,
             commands::rename_file,
             commands::delete_file,
@@ -46,7 +68,14 @@ pub fn run() {
             commands::open_in_folder,
             commands::expand_folder,
             commands::expand_parsed_folder,
+            commands::export_tree,
+            commands::set_access_token,
+            commands::remove_access_token,
+            commands::get_token_hosts,
+            commands::get_exclusions,
+            commands::set_exclusions,
+            commands::reset_exclusions,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
-}
\ No newline at end of file
+}
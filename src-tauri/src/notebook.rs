@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::Path;
+
+pub fn is_notebook(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ipynb"))
+}
+
+/// Renders a notebook as its markdown and code cells in order, dropping the JSON envelope,
+/// cell metadata and any image/binary payloads.
+pub fn extract_notebook(raw: &str, include_outputs: bool) -> Result<String> {
+    let notebook: Value = serde_json::from_str(raw).context("Notebook is not valid JSON")?;
+    let cells = notebook
+        .get("cells")
+        .and_then(Value::as_array)
+        .context("Notebook has no cells array")?;
+
+    let language = notebook_language(&notebook);
+    let mut out = String::new();
+
+    for cell in cells {
+        let source = join_text(cell.get("source"));
+        let cell_type = cell
+            .get("cell_type")
+            .and_then(Value::as_str)
+            .unwrap_or("raw");
+
+        match cell_type {
+            "code" => {
+                let header = match cell.get("execution_count").and_then(Value::as_u64) {
+                    Some(count) => format!("--- code ({}) [{}] ---", language, count),
+                    None => format!("--- code ({}) ---", language),
+                };
+                push_section(&mut out, &header, &source);
+
+                if include_outputs {
+                    if let Some(outputs) = cell.get("outputs").and_then(Value::as_array) {
+                        for output in outputs {
+                            if let Some(text) = render_output(output) {
+                                push_section(&mut out, "--- output ---", &text);
+                            }
+                        }
+                    }
+                }
+            }
+            other => push_section(&mut out, &format!("--- {} ---", other), &source),
+        }
+    }
+
+    Ok(out)
+}
+
+fn render_output(output: &Value) -> Option<String> {
+    match output.get("output_type").and_then(Value::as_str)? {
+        "stream" => Some(join_text(output.get("text"))),
+        "execute_result" | "display_data" => {
+            let data = output.get("data")?.as_object()?;
+            if let Some(text) = data.get("text/plain") {
+                return Some(join_text(Some(text)));
+            }
+
+            // Only rich/binary payloads (images, HTML widgets); name them instead of inlining.
+            let kinds: Vec<&str> = data.keys().map(String::as_str).collect();
+            Some(format!("[{} output omitted]", kinds.join(", ")))
+        }
+        "error" => {
+            let name = output
+                .get("ename")
+                .and_then(Value::as_str)
+                .unwrap_or("Error");
+            let value = output
+                .get("evalue")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            Some(format!("{}: {}", name, value))
+        }
+        _ => None,
+    }
+}
+
+fn notebook_language(notebook: &Value) -> &str {
+    let metadata = notebook.get("metadata");
+    metadata
+        .and_then(|m| m.pointer("/language_info/name"))
+        .or_else(|| metadata.and_then(|m| m.pointer("/kernelspec/language")))
+        .and_then(Value::as_str)
+        .unwrap_or("python")
+}
+
+/// Cell sources and stream texts are either a single string or a list of lines.
+fn join_text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+fn push_section(out: &mut String, header: &str, body: &str) {
+    if body.trim().is_empty() {
+        return;
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(header);
+    out.push('\n');
+    out.push_str(body.trim_end());
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTEBOOK: &str = r##"{
+        "metadata": {"kernelspec": {"language": "python"}},
+        "cells": [
+            {"cell_type": "markdown", "source": ["# Title\n", "Intro"]},
+            {
+                "cell_type": "code",
+                "execution_count": 1,
+                "source": "print('hi')",
+                "outputs": [
+                    {"output_type": "stream", "text": ["hi\n"]},
+                    {"output_type": "display_data", "data": {"image/png": "iVBORw0KGgo="}},
+                    {
+                        "output_type": "execute_result",
+                        "data": {"text/plain": "42", "image/png": "iVBORw0KGgo="}
+                    }
+                ]
+            },
+            {"cell_type": "code", "source": "", "outputs": []},
+            {"cell_type": "raw", "source": "plain"}
+        ]
+    }"##;
+
+    #[test]
+    fn keeps_cells_in_order_without_outputs() {
+        let text = extract_notebook(NOTEBOOK, false).unwrap();
+
+        assert_eq!(
+            text,
+            "--- markdown ---\n# Title\nIntro\n\n\
+             --- code (python) [1] ---\nprint('hi')\n\n\
+             --- raw ---\nplain\n"
+        );
+    }
+
+    #[test]
+    fn renders_text_outputs_and_drops_binary_payloads() {
+        let text = extract_notebook(NOTEBOOK, true).unwrap();
+
+        assert!(text.contains(
+            "print('hi')\n\n--- output ---\nhi\n\n\
+             --- output ---\n[image/png output omitted]\n\n\
+             --- output ---\n42\n"
+        ));
+        assert!(!text.contains("iVBORw0KGgo"));
+    }
+
+    #[test]
+    fn rejects_json_without_cells() {
+        assert!(extract_notebook("{}", false).is_err());
+        assert!(extract_notebook("not json", false).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::archive;
+use crate::credentials;
+use crate::remote::{self, Provider};
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Changed files per page of the PR files endpoint (the API maximum).
+const FILES_PER_PAGE: usize = 100;
+/// GitHub lists at most 3000 changed files for a pull request.
+const MAX_FILE_PAGES: usize = 30;
+/// Neighbouring files pulled in next to the changed ones, in total.
+pub const MAX_NEIGHBOR_FILES: usize = 100;
+/// Neighbours larger than this are left out; they are context, not the change.
+const MAX_NEIGHBOR_SIZE: u64 = 512 * 1024;
+
+/// A pull request URL such as `https://github.com/owner/repo/pull/42`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequestRef {
+    pub base_url: String,
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+impl PullRequestRef {
+    pub fn url(&self) -> String {
+        format!(
+            "{}/{}/{}/pull/{}",
+            self.base_url, self.owner, self.repo, self.number
+        )
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!(
+            "{}/repos/{}/{}{}",
+            remote::github_api_base(&self.base_url),
+            self.owner,
+            self.repo,
+            path
+        )
+    }
+}
+
+/// What was fetched for a pull request; the files are at their repository paths under `root`.
+pub struct PullRequestBundle {
+    pub root: PathBuf,
+    pub title: String,
+    pub diff: String,
+    pub head_ref: String,
+    pub head_sha: String,
+    pub changed_files: Vec<String>,
+    pub neighbor_files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PullResponse {
+    title: String,
+    head: PullHead,
+}
+
+#[derive(Deserialize)]
+struct PullHead {
+    #[serde(rename = "ref")]
+    name: String,
+    sha: String,
+    /// `None` when the fork the PR comes from was deleted.
+    repo: Option<PullRepo>,
+}
+
+#[derive(Deserialize)]
+struct PullRepo {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct PullFile {
+    filename: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ContentEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+    size: u64,
+}
+
+/// Recognises `<host>/<owner>/<repo>/pull/<number>` with anything after the number (`/files`,
+/// `/commits`, `#discussion…`).
+pub fn parse_pr_url(url: &str) -> Option<PullRequestRef> {
+    let (base_url, path) = remote::split_web_url(url.trim())?;
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match segments.as_slice() {
+        [owner, repo, "pull", number, ..] => Some(PullRequestRef {
+            base_url: base_url.to_string(),
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            number: number.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// Fetches the diff of `pr` and the head versions of its changed files (plus, with
+/// `include_neighbors`, the other files in the same directories) into `dest/<repo>`. Removed
+/// files only show up in the diff.
+pub fn fetch_pull_request(
+    client: &Client,
+    pr: &PullRequestRef,
+    token: Option<&str>,
+    dest: &Path,
+    include_neighbors: bool,
+) -> Result<PullRequestBundle> {
+    let api = |path: &str| {
+        let request = client.get(pr.api_url(path));
+        match token {
+            Some(token) => credentials::authorize(request, Provider::GitHub, token),
+            None => request,
+        }
+    };
+
+    let pull: PullResponse = send_json(api(&format!("/pulls/{}", pr.number)), pr)?;
+    let diff = send(
+        api(&format!("/pulls/{}", pr.number)).header("Accept", "application/vnd.github.v3.diff"),
+        pr,
+    )?
+    .text()?;
+
+    // Fork PRs have their head commit in the fork.
+    let head_repo = pull
+        .head
+        .repo
+        .as_ref()
+        .map(|r| r.full_name.clone())
+        .unwrap_or_else(|| format!("{}/{}", pr.owner, pr.repo));
+    let contents_api = |path: &str| {
+        let url = format!(
+            "{}/repos/{}/contents/{}?ref={}",
+            remote::github_api_base(&pr.base_url),
+            head_repo,
+            remote::encode_path(path),
+            pull.head.sha
+        );
+        let request = client.get(url);
+        match token {
+            Some(token) => credentials::authorize(request, Provider::GitHub, token),
+            None => request,
+        }
+    };
+
+    let mut changed_files = Vec::new();
+    for page in 1..=MAX_FILE_PAGES {
+        let files: Vec<PullFile> = send_json(
+            api(&format!(
+                "/pulls/{}/files?per_page={}&page={}",
+                pr.number, FILES_PER_PAGE, page
+            )),
+            pr,
+        )?;
+        let last_page = files.len() < FILES_PER_PAGE;
+        changed_files.extend(
+            files
+                .into_iter()
+                .filter(|file| file.status != "removed")
+                .map(|file| file.filename),
+        );
+        if last_page {
+            break;
+        }
+    }
+
+    let root = dest.join(&pr.repo);
+    fs::create_dir_all(&root)?;
+
+    for path in &changed_files {
+        let raw = contents_api(path).header("Accept", "application/vnd.github.raw");
+        let bytes = send(raw, pr)?.bytes()?;
+        write_file(&root, path, &bytes)?;
+    }
+
+    let mut neighbor_files = Vec::new();
+    if include_neighbors {
+        let changed: BTreeSet<&str> = changed_files.iter().map(String::as_str).collect();
+        let dirs: BTreeSet<&str> = changed_files
+            .iter()
+            .map(|path| path.rsplit_once('/').map_or("", |(dir, _)| dir))
+            .collect();
+
+        'dirs: for dir in dirs {
+            let listing: Vec<ContentEntry> = send_json(contents_api(dir), pr)?;
+            for entry in listing {
+                if neighbor_files.len() >= MAX_NEIGHBOR_FILES {
+                    break 'dirs;
+                }
+                if entry.kind != "file"
+                    || entry.size > MAX_NEIGHBOR_SIZE
+                    || changed.contains(entry.path.as_str())
+                {
+                    continue;
+                }
+
+                let raw = contents_api(&entry.path).header("Accept", "application/vnd.github.raw");
+                let bytes = send(raw, pr)?.bytes()?;
+                write_file(&root, &entry.path, &bytes)?;
+                neighbor_files.push(entry.path);
+            }
+        }
+    }
+
+    Ok(PullRequestBundle {
+        root,
+        title: pull.title,
+        diff,
+        head_ref: pull.head.name,
+        head_sha: pull.head.sha,
+        changed_files,
+        neighbor_files,
+    })
+}
+
+fn write_file(root: &Path, path: &str, bytes: &[u8]) -> Result<()> {
+    let relative = archive::safe_relative_path(Path::new(path))
+        .with_context(|| format!("Refusing file path '{}' outside the repository", path))?;
+    let target = root.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&target, bytes)?;
+    Ok(())
+}
+
+fn send(request: RequestBuilder, pr: &PullRequestRef) -> Result<reqwest::blocking::Response> {
+    let response = request.send()?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    Err(match status.as_u16() {
+        401 => anyhow::anyhow!(
+            "GitHub rejected the request for {} (HTTP 401). Check the access token.",
+            pr.url()
+        ),
+        403 => anyhow::anyhow!(
+            "Access to {} was denied (HTTP 403). The token may lack access or the API rate limit was hit.",
+            pr.url()
+        ),
+        404 => anyhow::anyhow!(
+            "{} was not found (HTTP 404). If the repository is private, add an access token.",
+            pr.url()
+        ),
+        _ => anyhow::anyhow!("GitHub API request for {} failed: HTTP {}", pr.url(), status),
+    })
+}
+
+fn send_json<T: for<'de> Deserialize<'de>>(
+    request: RequestBuilder,
+    pr: &PullRequestRef,
+) -> Result<T> {
+    let response = send(request.header("Accept", "application/vnd.github+json"), pr)?;
+    Ok(serde_json::from_reader(response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockRequest, MockResponse, MockServer, TempDir};
+
+    const API: &str = "/api/v3/repos/o/r";
+
+    /// Page 1 of the changed files is full, so a second page is asked for. It renames one file
+    /// and removes two, one of them on the second page.
+    fn changed_files_page(page: &str) -> String {
+        let mut files: Vec<String> = Vec::new();
+        if page == "1" {
+            files.extend(
+                (0..98).map(|i| format!(r#"{{"filename":"src/f{}.rs","status":"modified"}}"#, i)),
+            );
+            files.push(
+                r#"{"filename":"src/new.rs","status":"renamed","previous_filename":"src/old.rs"}"#
+                    .to_string(),
+            );
+            files.push(r#"{"filename":"src/dropped.rs","status":"removed"}"#.to_string());
+        } else {
+            files.push(r#"{"filename":"docs/read me.md","status":"added"}"#.to_string());
+            files.push(r#"{"filename":"gone.txt","status":"removed"}"#.to_string());
+        }
+        format!("[{}]", files.join(","))
+    }
+
+    fn github_api(request: &MockRequest) -> MockResponse {
+        let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+        let path = path.replace("%20", " ");
+        let accept = request.header("accept").unwrap_or_default();
+
+        if path == format!("{}/pulls/7", API) {
+            return if accept.contains("diff") {
+                MockResponse::new(200, "diff --git a/src/old.rs b/src/new.rs\n")
+            } else {
+                MockResponse::new(
+                    200,
+                    r#"{"title":"Rename","head":{"ref":"feature","sha":"abc123","repo":{"full_name":"o/r"}}}"#,
+                )
+            };
+        }
+        if path == format!("{}/pulls/7/files", API) {
+            let page = query.rsplit("page=").next().unwrap_or_default();
+            return MockResponse::new(200, changed_files_page(page));
+        }
+        if let Some(file) = path.strip_prefix(&format!("{}/contents/", API)) {
+            assert_eq!(query, "ref=abc123");
+            return if accept.contains("raw") {
+                MockResponse::new(200, format!("head of {}", file))
+            } else if file == "docs" {
+                MockResponse::new(
+                    200,
+                    r#"[{"path":"docs/read me.md","type":"file","size":10},
+                        {"path":"docs/guide.md","type":"file","size":10},
+                        {"path":"docs/img","type":"dir","size":0}]"#,
+                )
+            } else {
+                MockResponse::new(200, "[]")
+            };
+        }
+        MockResponse::new(404, "")
+    }
+
+    #[test]
+    fn parses_pull_request_urls() {
+        let pr = parse_pr_url("https://github.com/o/r/pull/42/files#diff-1").unwrap();
+        assert_eq!(
+            (pr.owner.as_str(), pr.repo.as_str(), pr.number),
+            ("o", "r", 42)
+        );
+        assert!(parse_pr_url("https://github.com/o/r/issues/42").is_none());
+    }
+
+    #[test]
+    fn fetches_diff_then_head_files_across_pages() {
+        let server = MockServer::start(github_api);
+        let pr = parse_pr_url(&format!("{}/o/r/pull/7", server.base_url())).unwrap();
+        let dest = TempDir::new();
+
+        let bundle = fetch_pull_request(&Client::new(), &pr, None, dest.path(), true).unwrap();
+        assert_eq!(bundle.title, "Rename");
+        assert_eq!(bundle.head_ref, "feature");
+        assert!(bundle
+            .diff
+            .starts_with("diff --git a/src/old.rs b/src/new.rs"));
+
+        // Renamed files come at their new path; removed ones only show up in the diff.
+        assert_eq!(bundle.changed_files.len(), 100);
+        assert!(bundle.changed_files.contains(&"src/new.rs".to_string()));
+        assert!(bundle
+            .changed_files
+            .contains(&"docs/read me.md".to_string()));
+        for removed in ["src/old.rs", "src/dropped.rs", "gone.txt"] {
+            assert!(!bundle.changed_files.contains(&removed.to_string()));
+            assert!(!bundle.root.join(removed).exists());
+        }
+        assert_eq!(
+            fs::read_to_string(bundle.root.join("docs/read me.md")).unwrap(),
+            "head of docs/read me.md"
+        );
+        assert_eq!(bundle.neighbor_files, ["docs/guide.md"]);
+
+        // The pull request and its diff are asked for before any file.
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths[0], format!("{}/pulls/7", API));
+        assert_eq!(paths[1], format!("{}/pulls/7", API));
+        assert_eq!(
+            paths[2],
+            format!("{}/pulls/7/files?per_page=100&page=1", API)
+        );
+        assert_eq!(
+            paths[3],
+            format!("{}/pulls/7/files?per_page=100&page=2", API)
+        );
+        assert!(paths[4..]
+            .iter()
+            .all(|path| path.starts_with(&format!("{}/contents/", API))));
+    }
+}
@@ -0,0 +1,458 @@
+use crate::archive;
+use crate::git;
+use crate::repo_info::RepoInfo;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+/// Written next to every fetched repository in `temp-repos/<id>/` so `parse_files` can tell where
+/// the files came from.
+pub const SOURCE_FILENAME: &str = "source.json";
+
+/// Hosting service a repository URL points at, which decides how its files can be fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    #[serde(rename = "github")]
+    GitHub,
+    #[serde(rename = "gitlab")]
+    GitLab,
+    Bitbucket,
+    /// Gitea and its fork Forgejo (Codeberg) share URLs and archive endpoints.
+    #[serde(alias = "forgejo")]
+    Gitea,
+    /// Direct link to a `.zip`/`.tar.gz`/`.tar.xz` file rather than to a repository.
+    Archive,
+    /// Anything else: only reachable with a git clone.
+    Git,
+}
+
+impl Provider {
+    /// Guesses the provider of a well-known host. Self-hosted instances are recognised when the
+    /// product name is part of the host (`gitlab.example.com`); otherwise pass the provider
+    /// explicitly.
+    pub fn from_host(host: &str) -> Self {
+        let host = host.to_lowercase();
+        if host == "github.com" || host == "www.github.com" {
+            Provider::GitHub
+        } else if host == "bitbucket.org" {
+            Provider::Bitbucket
+        } else if host.contains("gitlab") {
+            Provider::GitLab
+        } else if host == "codeberg.org" || host.contains("gitea") || host.contains("forgejo") {
+            Provider::Gitea
+        } else {
+            Provider::Git
+        }
+    }
+
+    /// Path segments that introduce `<ref>/<subdir>` in the provider's browser URLs.
+    fn browse_markers(self) -> &'static [&'static [&'static str]] {
+        match self {
+            Provider::GitHub => &[&["tree"], &["blob"]],
+            Provider::GitLab => &[&["-", "tree"], &["-", "blob"]],
+            Provider::Bitbucket => &[&["src"]],
+            Provider::Gitea => &[&["src", "branch"], &["src", "tag"], &["src", "commit"]],
+            Provider::Archive | Provider::Git => &[],
+        }
+    }
+}
+
+/// A repository URL split into the repository itself and the revision/subdirectory it points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoLocation {
+    pub provider: Provider,
+    /// The repository (or archive) URL without any browser path after it.
+    pub url: String,
+    /// `scheme://host[:port]` of web URLs, empty otherwise.
+    pub base_url: String,
+    /// `owner/repo` (or `group/subgroup/repo` on GitLab) without a `.git` suffix.
+    pub repo_path: String,
+    pub reference: Option<String>,
+    pub subdir: Option<String>,
+}
+
+impl RepoLocation {
+    /// Directory name for the fetched files.
+    pub fn name(&self) -> String {
+        match self.provider {
+            Provider::Archive => {
+                let file_name = self
+                    .url
+                    .split(['?', '#'])
+                    .next()
+                    .unwrap_or_default()
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default();
+                let stem = ARCHIVE_SUFFIXES
+                    .iter()
+                    .find_map(|suffix| file_name.strip_suffix(suffix))
+                    .unwrap_or(file_name);
+                if stem.is_empty() {
+                    "archive".to_string()
+                } else {
+                    stem.to_string()
+                }
+            }
+            _ => git::repo_name_from_url(&self.url),
+        }
+    }
+
+    /// `host[:port]` of web URLs.
+    pub fn host(&self) -> &str {
+        self.base_url.split_once("://").map_or("", |(_, host)| host)
+    }
+
+    /// URL of a snapshot of `reference`, or `None` when the provider has no archive endpoint.
+    /// `authenticated` switches to the REST API endpoints, which accept access tokens where the
+    /// browser download links only accept session cookies.
+    pub fn archive_url(&self, reference: &str, authenticated: bool) -> Option<String> {
+        let repo = format!("{}/{}", self.base_url, self.repo_path);
+        let encoded = encode_component(reference);
+        match (self.provider, authenticated) {
+            (Provider::GitHub, false) | (Provider::Gitea, false) => {
+                Some(format!("{}/archive/{}.zip", repo, encoded))
+            }
+            (Provider::GitHub, true) => Some(format!(
+                "{}/repos/{}/zipball/{}",
+                github_api_base(&self.base_url),
+                self.repo_path,
+                encoded
+            )),
+            (Provider::GitLab, false) => {
+                let name = self.repo_path.rsplit('/').next().unwrap_or_default();
+                let file_ref = encode_component(&reference.replace('/', "-"));
+                Some(format!(
+                    "{}/-/archive/{}/{}-{}.zip",
+                    repo, encoded, name, file_ref
+                ))
+            }
+            (Provider::GitLab, true) => Some(format!(
+                "{}/api/v4/projects/{}/repository/archive.zip?sha={}",
+                self.base_url,
+                self.repo_path.replace('/', "%2F"),
+                encoded
+            )),
+            (Provider::Gitea, true) => Some(format!(
+                "{}/api/v1/repos/{}/archive/{}.zip",
+                self.base_url, self.repo_path, encoded
+            )),
+            (Provider::Bitbucket, _) => Some(format!("{}/get/{}.zip", repo, encoded)),
+            (Provider::Archive, _) => Some(self.url.clone()),
+            (Provider::Git, _) => None,
+        }
+    }
+
+    /// URL to clone with git; direct archive links have none.
+    pub fn clone_url(&self) -> Option<String> {
+        match self.provider {
+            Provider::Git => Some(self.url.clone()),
+            Provider::Archive => None,
+            _ => Some(format!("{}/{}.git", self.base_url, self.repo_path)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteSource {
+    pub url: String,
+    pub reference: Option<String>,
+    pub commit: Option<String>,
+    pub subdir: Option<String>,
+    /// Bare clone kept next to the files when they were fetched with git.
+    pub git_dir: Option<String>,
+    /// Details from the hosting service's API, when it has one and was reachable.
+    #[serde(default)]
+    pub repository: Option<RepoInfo>,
+    /// Files a sparse fetch left out for their size, relative to `subdir`.
+    #[serde(default)]
+    pub too_large: Vec<String>,
+}
+
+impl RemoteSource {
+    pub fn save(&self, repo_root: &Path) -> Result<()> {
+        let file = File::create(repo_root.join(SOURCE_FILENAME))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Finds the source record of a path inside `temp_repos_root`, if it was fetched remotely.
+    pub fn find(path: &Path, temp_repos_root: &Path) -> Option<Self> {
+        let repo_root = repo_root_of(path, temp_repos_root)?;
+        let file = File::open(repo_root.join(SOURCE_FILENAME)).ok()?;
+        serde_json::from_reader(io::BufReader::new(file)).ok()
+    }
+}
+
+/// `temp-repos/<id>` for any path below it.
+pub fn repo_root_of(path: &Path, temp_repos_root: &Path) -> Option<PathBuf> {
+    let stripped = path.strip_prefix(temp_repos_root).ok()?;
+    let first_component = stripped.components().next()?;
+    Some(temp_repos_root.join(first_component.as_os_str()))
+}
+
+/// `temp-repos/<id>/<name>`, the fetched files (whole tree, not a subdirectory) a path below
+/// `temp_repos_root` belongs to.
+pub fn files_root_of(path: &Path, temp_repos_root: &Path) -> Option<PathBuf> {
+    let repo_root = repo_root_of(path, temp_repos_root)?;
+    let first = path.strip_prefix(&repo_root).ok()?.components().next()?;
+    Some(repo_root.join(first))
+}
+
+const ARCHIVE_SUFFIXES: &[&str] = &[".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar", ".zip"];
+
+/// Splits browser URLs such as `https://github.com/owner/repo/tree/<ref>/<subdir>` (or the
+/// GitLab, Bitbucket and Gitea equivalents) into the repository URL, ref and subdirectory. An
+/// explicit `reference` wins over the one in the URL and tells where a ref containing slashes
+/// ends; otherwise the first segment after the marker is taken as the ref.
+///
+/// `provider` overrides host-based detection, e.g. for a self-hosted GitLab on a custom domain.
+/// Links ending in an archive extension are treated as direct downloads. An explicit ref that
+/// the URL's `<ref>/<subdir>` part does not start with, and subdirectories climbing out with
+/// `..`, are errors.
+pub fn parse_repo_url(
+    input: &str,
+    reference: Option<&str>,
+    provider: Option<Provider>,
+) -> Result<RepoLocation> {
+    let trimmed = input.trim().trim_end_matches('/');
+    let explicit_ref = reference.map(str::trim).filter(|r| !r.is_empty());
+
+    let Some((base_url, path)) = split_web_url(trimmed) else {
+        return Ok(RepoLocation {
+            provider: Provider::Git,
+            url: trimmed.to_string(),
+            base_url: String::new(),
+            repo_path: String::new(),
+            reference: explicit_ref.map(str::to_string),
+            subdir: None,
+        });
+    };
+
+    let host = base_url.split("://").nth(1).unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let path_only = path.split(['?', '#']).next().unwrap_or_default();
+    let provider = provider.unwrap_or_else(|| {
+        if archive::archive_kind(Path::new(path_only)).is_some() {
+            Provider::Archive
+        } else {
+            Provider::from_host(host)
+        }
+    });
+
+    if matches!(provider, Provider::Archive | Provider::Git) {
+        return Ok(RepoLocation {
+            provider,
+            url: trimmed.to_string(),
+            base_url: base_url.to_string(),
+            repo_path: path_only.to_string(),
+            reference: explicit_ref.map(str::to_string),
+            subdir: None,
+        });
+    }
+
+    let segments: Vec<&str> = path_only.split('/').filter(|s| !s.is_empty()).collect();
+    let (repo_len, tree_path) = split_browse_path(provider, &segments);
+    let repo_path = segments[..repo_len].join("/");
+
+    let (url_ref, subdir) = match (tree_path.as_deref(), explicit_ref) {
+        (Some(path), Some(explicit)) => match path.strip_prefix(explicit) {
+            Some("") => (None, None),
+            Some(rest) if rest.starts_with('/') => (None, Some(rest[1..].to_string())),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Ref '{}' does not match the start of '{}' in {}",
+                    explicit,
+                    path,
+                    trimmed
+                ))
+            }
+        },
+        (Some(path), None) => match path.split_once('/') {
+            Some((r, rest)) => (Some(r.to_string()), Some(rest.to_string())),
+            None => (Some(path.to_string()), None),
+        },
+        (None, _) => (None, None),
+    };
+
+    let subdir = match subdir.filter(|s| !s.is_empty()) {
+        Some(subdir) => Some(safe_subdir(&subdir)?),
+        None => None,
+    };
+
+    Ok(RepoLocation {
+        provider,
+        url: format!("{}/{}", base_url, repo_path),
+        base_url: base_url.to_string(),
+        repo_path: repo_path.trim_end_matches(".git").to_string(),
+        reference: explicit_ref.map(str::to_string).or(url_ref),
+        subdir: subdir.filter(|s| !s.is_empty()),
+    })
+}
+
+/// `subdir` as a `/`-separated path that stays inside the repository.
+pub fn safe_subdir(subdir: &str) -> Result<String> {
+    let relative = archive::safe_relative_path(Path::new(subdir))
+        .with_context(|| format!("Invalid subdirectory '{}'", subdir))?;
+    Ok(relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// Number of segments naming the repository and the `<ref>/<subdir>` part after the provider's
+/// browse marker, if any. Repositories are at least `owner/repo` deep.
+fn split_browse_path(provider: Provider, segments: &[&str]) -> (usize, Option<String>) {
+    for start in 2..segments.len() {
+        for marker in provider.browse_markers() {
+            if segments[start..].starts_with(marker) {
+                let rest = &segments[start + marker.len()..];
+                let rest = (!rest.is_empty()).then(|| rest.join("/"));
+                return (start, rest);
+            }
+        }
+    }
+    (segments.len(), None)
+}
+
+/// REST API root for a GitHub host: `api.github.com` for github.com, `/api/v3` on Enterprise
+/// Server (and on any other host, which is what a local mock of the API looks like).
+pub fn github_api_base(base_url: &str) -> String {
+    match base_url.split_once("://") {
+        Some((_, "github.com")) | Some((_, "www.github.com")) => {
+            "https://api.github.com".to_string()
+        }
+        _ => format!("{}/api/v3", base_url),
+    }
+}
+
+/// `("https://host", "path")` for http(s) URLs.
+pub fn split_web_url(url: &str) -> Option<(&str, &str)> {
+    let scheme_len = if url.starts_with("https://") {
+        "https://".len()
+    } else if url.starts_with("http://") {
+        "http://".len()
+    } else {
+        return None;
+    };
+
+    match url[scheme_len..].find('/') {
+        Some(slash) => Some((&url[..scheme_len + slash], &url[scheme_len + slash + 1..])),
+        None => Some((url, "")),
+    }
+}
+
+/// Escapes the characters that would end or break the path part of a URL.
+pub fn encode_path(path: &str) -> String {
+    path.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('#', "%23")
+        .replace('?', "%3F")
+}
+
+/// Like `encode_path`, but for a path passed as a single URL component.
+pub fn encode_component(path: &str) -> String {
+    encode_path(path).replace('/', "%2F").replace('&', "%26")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_ref_must_match_tree_path() {
+        let location = parse_repo_url(
+            "https://github.com/o/r/tree/feature/x/src/app",
+            Some("feature/x"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(location.reference.as_deref(), Some("feature/x"));
+        assert_eq!(location.subdir.as_deref(), Some("src/app"));
+
+        let mismatch = parse_repo_url(
+            "https://github.com/o/r/tree/main/src",
+            Some("develop"),
+            None,
+        );
+        assert!(mismatch.is_err());
+    }
+
+    #[test]
+    fn rejects_subdir_outside_repository() {
+        let climbing = parse_repo_url("https://github.com/o/r/tree/main/src/../../etc", None, None);
+        assert!(climbing.is_err());
+
+        let location =
+            parse_repo_url("https://github.com/o/r/tree/main/./src", None, None).unwrap();
+        assert_eq!(location.subdir.as_deref(), Some("src"));
+    }
+
+    #[test]
+    fn parses_provider_browse_urls() {
+        let cases = [
+            (
+                "https://github.com/o/r/tree/main/src",
+                Provider::GitHub,
+                "o/r",
+                "https://github.com/o/r/archive/main.zip",
+            ),
+            (
+                "https://gitlab.com/group/sub/r/-/tree/main/src",
+                Provider::GitLab,
+                "group/sub/r",
+                "https://gitlab.com/group/sub/r/-/archive/main/r-main.zip",
+            ),
+            (
+                "https://bitbucket.org/o/r/src/main/src",
+                Provider::Bitbucket,
+                "o/r",
+                "https://bitbucket.org/o/r/get/main.zip",
+            ),
+            (
+                "https://codeberg.org/o/r/src/branch/main/src",
+                Provider::Gitea,
+                "o/r",
+                "https://codeberg.org/o/r/archive/main.zip",
+            ),
+        ];
+        for (url, provider, repo_path, archive_url) in cases {
+            let location = parse_repo_url(url, None, None).unwrap();
+            assert_eq!(location.provider, provider, "{}", url);
+            assert_eq!(location.repo_path, repo_path, "{}", url);
+            assert_eq!(location.reference.as_deref(), Some("main"), "{}", url);
+            assert_eq!(location.subdir.as_deref(), Some("src"), "{}", url);
+            assert_eq!(
+                location.archive_url("main", false).as_deref(),
+                Some(archive_url)
+            );
+        }
+    }
+
+    #[test]
+    fn authenticated_archive_urls_use_the_api() {
+        let github = parse_repo_url("https://github.com/o/r", None, None).unwrap();
+        assert_eq!(
+            github.archive_url("main", true).as_deref(),
+            Some("https://api.github.com/repos/o/r/zipball/main")
+        );
+
+        let gitlab = parse_repo_url("https://gitlab.com/group/sub/r", None, None).unwrap();
+        assert_eq!(
+            gitlab.archive_url("main", true).as_deref(),
+            Some("https://gitlab.com/api/v4/projects/group%2Fsub%2Fr/repository/archive.zip?sha=main")
+        );
+
+        let gitea = parse_repo_url("https://codeberg.org/o/r", None, None).unwrap();
+        assert_eq!(
+            gitea.archive_url("main", true).as_deref(),
+            Some("https://codeberg.org/api/v1/repos/o/r/archive/main.zip")
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use crate::credentials;
+use crate::remote::{self, Provider, RepoLocation};
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What the hosting service says about a repository. Fields a provider does not report stay
+/// empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepoInfo {
+    pub description: Option<String>,
+    pub default_branch: Option<String>,
+    /// SPDX identifier where the host reports one, otherwise the license name.
+    pub license: Option<String>,
+    pub language: Option<String>,
+    pub topics: Vec<String>,
+}
+
+/// Looks `location` up in its provider's REST API. Plain git and archive links have no API and
+/// yield `None`.
+pub fn fetch_repo_info(
+    client: &Client,
+    location: &RepoLocation,
+    token: Option<&str>,
+) -> Result<Option<RepoInfo>> {
+    let api = RepoApi::new(client, location, token);
+
+    let info = match location.provider {
+        Provider::GitHub => {
+            let repo = api.json(&api.github(""))?;
+            RepoInfo {
+                description: string_at(&repo, &["description"]),
+                default_branch: string_at(&repo, &["default_branch"]),
+                license: string_at(&repo, &["license", "spdx_id"])
+                    .filter(|id| id != "NOASSERTION")
+                    .or_else(|| string_at(&repo, &["license", "name"])),
+                language: string_at(&repo, &["language"]),
+                topics: strings_at(&repo, "topics"),
+            }
+        }
+        Provider::GitLab => {
+            let project = api.json(&format!("{}?license=true", api.gitlab("")))?;
+            let languages = api.json(&api.gitlab("/languages")).ok();
+            RepoInfo {
+                description: string_at(&project, &["description"]),
+                default_branch: string_at(&project, &["default_branch"]),
+                license: string_at(&project, &["license", "nickname"])
+                    .or_else(|| string_at(&project, &["license", "name"])),
+                language: languages.as_ref().and_then(main_language),
+                topics: match strings_at(&project, "topics") {
+                    topics if topics.is_empty() => strings_at(&project, "tag_list"),
+                    topics => topics,
+                },
+            }
+        }
+        Provider::Gitea => {
+            let repo = api.json(&api.gitea(""))?;
+            let languages = api.json(&api.gitea("/languages")).ok();
+            RepoInfo {
+                description: string_at(&repo, &["description"]),
+                default_branch: string_at(&repo, &["default_branch"]),
+                license: strings_at(&repo, "licenses").into_iter().next(),
+                language: languages.as_ref().and_then(main_language),
+                topics: strings_at(&repo, "topics"),
+            }
+        }
+        Provider::Bitbucket => {
+            let repo = api.json(&api.bitbucket(""))?;
+            RepoInfo {
+                description: string_at(&repo, &["description"]),
+                default_branch: string_at(&repo, &["mainbranch", "name"]),
+                license: None,
+                language: string_at(&repo, &["language"]),
+                topics: Vec::new(),
+            }
+        }
+        Provider::Archive | Provider::Git => return Ok(None),
+    };
+
+    Ok(Some(info))
+}
+
+/// The commit `reference` points to, for downloads whose archive does not name it. `None`
+/// stands for the default branch.
+pub fn resolve_commit(
+    client: &Client,
+    location: &RepoLocation,
+    reference: Option<&str>,
+    token: Option<&str>,
+) -> Result<Option<String>> {
+    let api = RepoApi::new(client, location, token);
+
+    let sha = match (location.provider, reference) {
+        (Provider::GitHub, reference) => {
+            let url = api.github(&format!("/commits/{}", reference.unwrap_or("HEAD")));
+            let request = api.get(&url).header("Accept", "application/vnd.github.sha");
+            Some(api.send(request)?.text()?.trim().to_string())
+        }
+        (Provider::GitLab, Some(reference)) => {
+            let commit = api.json(&api.gitlab(&format!("/repository/commits/{}", reference)))?;
+            string_at(&commit, &["id"])
+        }
+        // Commit listings start at the tip of the default branch.
+        (Provider::GitLab, None) => {
+            let commits = api.json(&api.gitlab("/repository/commits?per_page=1"))?;
+            commits.get(0).and_then(|c| string_at(c, &["id"]))
+        }
+        (Provider::Gitea, reference) => {
+            let url = match reference {
+                Some(reference) => api.gitea(&format!("/commits?sha={}&limit=1", reference)),
+                None => api.gitea("/commits?limit=1"),
+            };
+            let commits = api.json(&url)?;
+            commits.get(0).and_then(|c| string_at(c, &["sha"]))
+        }
+        (Provider::Bitbucket, Some(reference)) => {
+            let commit = api.json(&api.bitbucket(&format!("/commit/{}", reference)))?;
+            string_at(&commit, &["hash"])
+        }
+        (Provider::Bitbucket, None) => {
+            let commits = api.json(&api.bitbucket("/commits?pagelen=1"))?;
+            commits
+                .get("values")
+                .and_then(|values| values.get(0))
+                .and_then(|c| string_at(c, &["hash"]))
+        }
+        (Provider::Archive | Provider::Git, _) => None,
+    };
+
+    Ok(sha.filter(|sha| !sha.is_empty()))
+}
+
+/// Plain-text header describing where a parse came from, one `Key: value` line per known field.
+pub fn header_text(
+    url: &str,
+    reference: Option<&str>,
+    commit: Option<&str>,
+    info: &RepoInfo,
+) -> String {
+    let topics = info.topics.join(", ");
+    let fields = [
+        ("Repository", Some(url)),
+        ("Description", info.description.as_deref()),
+        ("Ref", reference),
+        ("Commit", commit),
+        ("Default branch", info.default_branch.as_deref()),
+        ("License", info.license.as_deref()),
+        ("Language", info.language.as_deref()),
+        ("Topics", Some(topics.as_str())),
+    ];
+
+    fields
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = value?.trim();
+            (!value.is_empty()).then(|| format!("{}: {}\n", key, value))
+        })
+        .collect()
+}
+
+/// Requests against the REST API of one repository, authorized with its host's token.
+pub struct RepoApi<'a> {
+    client: &'a Client,
+    location: &'a RepoLocation,
+    token: Option<&'a str>,
+}
+
+impl<'a> RepoApi<'a> {
+    pub fn new(client: &'a Client, location: &'a RepoLocation, token: Option<&'a str>) -> Self {
+        Self {
+            client,
+            location,
+            token,
+        }
+    }
+
+    /// `<path>` below the repository on the GitHub (Enterprise) REST API.
+    pub fn github(&self, path: &str) -> String {
+        format!(
+            "{}/repos/{}{}",
+            remote::github_api_base(&self.location.base_url),
+            self.location.repo_path,
+            path
+        )
+    }
+
+    /// `<path>` below the project on the GitLab REST API.
+    pub fn gitlab(&self, path: &str) -> String {
+        format!(
+            "{}/api/v4/projects/{}{}",
+            self.location.base_url,
+            self.location.repo_path.replace('/', "%2F"),
+            path
+        )
+    }
+
+    /// `<path>` below the repository on the Gitea/Forgejo REST API.
+    pub fn gitea(&self, path: &str) -> String {
+        format!(
+            "{}/api/v1/repos/{}{}",
+            self.location.base_url, self.location.repo_path, path
+        )
+    }
+
+    /// `<path>` below the repository on the Bitbucket Cloud REST API.
+    pub fn bitbucket(&self, path: &str) -> String {
+        let api_base = match self.location.host() {
+            "bitbucket.org" | "www.bitbucket.org" => "https://api.bitbucket.org/2.0".to_string(),
+            _ => format!("{}/api/2.0", self.location.base_url),
+        };
+        format!(
+            "{}/repositories/{}{}",
+            api_base, self.location.repo_path, path
+        )
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        let request = self.client.get(url);
+        match self.token {
+            Some(token) => credentials::authorize(request, self.location.provider, token),
+            None => request,
+        }
+    }
+
+    pub fn send(&self, request: RequestBuilder) -> Result<reqwest::blocking::Response> {
+        let response = request.send()?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Repository API request for {} failed: HTTP {}",
+                self.location.url,
+                status
+            ));
+        }
+        Ok(response)
+    }
+
+    pub fn json(&self, url: &str) -> Result<Value> {
+        let response = self.send(self.get(url).header("Accept", "application/json"))?;
+        serde_json::from_reader(response)
+            .with_context(|| format!("Reading the repository API response for {}", url))
+    }
+}
+
+fn string_at(value: &Value, keys: &[&str]) -> Option<String> {
+    let mut current = value;
+    for key in keys {
+        current = current.get(key)?;
+    }
+    current
+        .as_str()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+fn strings_at(value: &Value, key: &str) -> Vec<String> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The largest entry of a `{ "language": share }` map, as returned by GitLab and Gitea.
+fn main_language(languages: &Value) -> Option<String> {
+    languages
+        .as_object()?
+        .iter()
+        .filter_map(|(name, share)| Some((name, share.as_f64()?)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(name, _)| name.clone())
+}
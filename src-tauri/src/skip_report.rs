@@ -0,0 +1,93 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+pub const SKIPPED_FILENAME: &str = "skipped.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Binary,
+    TooLarge,
+    PermissionDenied,
+    Ignored,
+    /// Also read for reports saved when undecodable text was skipped; it is transcoded now.
+    #[serde(alias = "invalid_utf8")]
+    ReadError,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub relative_path: String,
+    pub reason: SkipReason,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SkipSummary {
+    pub total: usize,
+    pub by_reason: BTreeMap<SkipReason, usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SkipReport {
+    pub files: Vec<SkippedFile>,
+}
+
+impl SkipReport {
+    pub fn record(
+        &mut self,
+        path: &Path,
+        relative_path: &Path,
+        reason: SkipReason,
+        detail: impl Into<String>,
+    ) {
+        self.files.push(SkippedFile {
+            path: path.to_string_lossy().to_string(),
+            relative_path: relative_path.to_string_lossy().to_string(),
+            reason,
+            detail: detail.into(),
+        });
+    }
+
+    pub fn summary(&self) -> SkipSummary {
+        let mut by_reason = BTreeMap::new();
+        for file in &self.files {
+            *by_reason.entry(file.reason).or_insert(0) += 1;
+        }
+
+        SkipSummary {
+            total: self.files.len(),
+            by_reason,
+        }
+    }
+
+    pub fn save(&self, parse_dir: &Path) -> Result<()> {
+        let file = File::create(parse_dir.join(SKIPPED_FILENAME))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn load(parse_dir: &Path) -> Result<Self> {
+        let path = parse_dir.join(SKIPPED_FILENAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+}
+
+/// Maps a read failure to the reason shown in the report.
+pub fn reason_for_error(err: &anyhow::Error) -> SkipReason {
+    match err.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::PermissionDenied) => SkipReason::PermissionDenied,
+        _ => SkipReason::ReadError,
+    }
+}
@@ -0,0 +1,324 @@
+use crate::archive;
+use crate::cache::FetchedTree;
+use crate::download::{DownloadHandle, DownloadOptions, DownloadProgress};
+use crate::remote::{encode_component, encode_path, Provider, RepoLocation};
+use crate::repo_info::{self, RepoApi};
+use crate::tree_export::format_size;
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Tree listing page size for GitLab (its maximum) and Gitea.
+const TREE_PAGE_SIZE: usize = 100;
+/// Listings longer than this fall back to the full archive.
+const MAX_TREE_PAGES: usize = 1000;
+
+/// A file below the requested subdirectory, with its size when the listing reports it.
+struct TreeFile {
+    path: String,
+    size: Option<u64>,
+}
+
+/// What a sparse fetch brought in, and what it left out for size.
+pub struct SparseFetch {
+    pub tree: FetchedTree,
+    /// Files above `DownloadOptions::max_file_size`, relative to the subdirectory.
+    pub too_large: Vec<String>,
+}
+
+/// Fetches only `location.subdir` through the provider's tree and raw file APIs into
+/// `dest_root/<name>/<subdir>`. Hidden paths and symlinks are left out like in local parses,
+/// and so are files above `options.max_file_size`, which are listed in the result so the parse
+/// can report them. Returns `None` when the provider has no such API or the listing is too
+/// large, so the caller can download the whole archive instead.
+pub fn fetch_subdirectory(
+    client: &Client,
+    location: &RepoLocation,
+    token: Option<&str>,
+    dest_root: &Path,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<Option<SparseFetch>> {
+    let Some(subdir) = location.subdir.as_deref() else {
+        return Ok(None);
+    };
+    if !matches!(
+        location.provider,
+        Provider::GitHub | Provider::GitLab | Provider::Gitea
+    ) {
+        return Ok(None);
+    }
+
+    let api = RepoApi::new(client, location, token);
+    let Some(commit) =
+        repo_info::resolve_commit(client, location, location.reference.as_deref(), token)?
+    else {
+        return Ok(None);
+    };
+    let listing = match location.provider {
+        Provider::GitHub => github_tree(&api, &commit)?,
+        Provider::GitLab => gitlab_tree(&api, &commit, subdir)?,
+        _ => gitea_tree(&api, &commit)?,
+    };
+    let Some(listing) = listing else {
+        return Ok(None);
+    };
+
+    let max_file_size = options.max_file_size;
+    let prefix = format!("{}/", subdir.trim_matches('/'));
+    let (files, oversized): (Vec<TreeFile>, Vec<TreeFile>) = listing
+        .into_iter()
+        .filter(|file| {
+            file.path
+                .strip_prefix(&prefix)
+                .is_some_and(|rest| !rest.split('/').any(|part| part.starts_with('.')))
+        })
+        .partition(|file| match (file.size, max_file_size) {
+            (Some(size), Some(max)) => size <= max,
+            _ => true,
+        });
+    let mut too_large: Vec<String> = oversized
+        .into_iter()
+        .map(|file| file.path[prefix.len()..].to_string())
+        .collect();
+    if files.is_empty() && too_large.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Path '{}' not found in {}",
+            subdir,
+            location.url
+        ));
+    }
+
+    let root = dest_root.join(location.name());
+    let total = files.iter().map(|file| file.size).sum::<Option<u64>>();
+    let mut received = 0u64;
+
+    for file in &files {
+        if handle.is_cancelled() {
+            return Err(anyhow::anyhow!("Download cancelled"));
+        }
+
+        let relative = archive::safe_relative_path(Path::new(&file.path)).with_context(|| {
+            format!("Refusing file path '{}' outside the repository", file.path)
+        })?;
+        let target = root.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let request = api
+            .get(&raw_url(&api, location, &commit, &file.path))
+            .header("Accept", "application/vnd.github.raw");
+        let response = api.send(request)?;
+        let limit = max_file_size.unwrap_or(u64::MAX);
+        let written = copy_limited(response, &target, limit)?;
+        if written > limit {
+            // The listing had no size to filter on; drop it like a local parse would.
+            fs::remove_file(&target)?;
+            too_large.push(file.path[prefix.len()..].to_string());
+            continue;
+        }
+
+        received += written;
+        if received > options.max_archive_size {
+            return Err(anyhow::anyhow!(
+                "{} is larger than the {} download limit",
+                subdir,
+                format_size(options.max_archive_size)
+            ));
+        }
+        on_progress(DownloadProgress {
+            download_id: handle.id().to_string(),
+            received,
+            total,
+        });
+    }
+
+    // Even with every file left out, the subdirectory exists.
+    fs::create_dir_all(root.join(&prefix))?;
+
+    Ok(Some(SparseFetch {
+        tree: FetchedTree {
+            files: root,
+            commit: Some(commit),
+            git_dir: None,
+        },
+        too_large,
+    }))
+}
+
+/// The whole recursive tree of `commit`; `None` when GitHub truncated it.
+fn github_tree(api: &RepoApi, commit: &str) -> Result<Option<Vec<TreeFile>>> {
+    let tree = api.json(&api.github(&format!("/git/trees/{}?recursive=1", commit)))?;
+    if tree.get("truncated").and_then(Value::as_bool) == Some(true) {
+        return Ok(None);
+    }
+    Ok(Some(blobs(&tree)))
+}
+
+fn gitlab_tree(api: &RepoApi, commit: &str, subdir: &str) -> Result<Option<Vec<TreeFile>>> {
+    let mut files = Vec::new();
+    for page in 1..=MAX_TREE_PAGES {
+        let url = api.gitlab(&format!(
+            "/repository/tree?path={}&ref={}&recursive=true&per_page={}&page={}",
+            encode_component(subdir),
+            commit,
+            TREE_PAGE_SIZE,
+            page
+        ));
+        let entries = api.json(&url)?;
+        let count = entries.as_array().map_or(0, Vec::len);
+        files.extend(blob_entries(&entries));
+        if count < TREE_PAGE_SIZE {
+            return Ok(Some(files));
+        }
+    }
+    Ok(None)
+}
+
+fn gitea_tree(api: &RepoApi, commit: &str) -> Result<Option<Vec<TreeFile>>> {
+    let mut files = Vec::new();
+    for page in 1..=MAX_TREE_PAGES {
+        let url = api.gitea(&format!(
+            "/git/trees/{}?recursive=true&per_page={}&page={}",
+            commit, TREE_PAGE_SIZE, page
+        ));
+        let tree = api.json(&url)?;
+        let count = tree
+            .get("tree")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len);
+        files.extend(blobs(&tree));
+        if tree.get("truncated").and_then(Value::as_bool) != Some(true) || count == 0 {
+            return Ok(Some(files));
+        }
+    }
+    Ok(None)
+}
+
+fn blobs(tree: &Value) -> Vec<TreeFile> {
+    tree.get("tree").map(blob_entries).unwrap_or_default()
+}
+
+/// Regular files of a tree listing; symlinks (mode 120000) and submodules are skipped.
+fn blob_entries(entries: &Value) -> Vec<TreeFile> {
+    entries
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            entry.get("type").and_then(Value::as_str) == Some("blob")
+                && entry.get("mode").and_then(Value::as_str) != Some("120000")
+        })
+        .filter_map(|entry| {
+            Some(TreeFile {
+                path: entry.get("path")?.as_str()?.to_string(),
+                size: entry.get("size").and_then(Value::as_u64),
+            })
+        })
+        .collect()
+}
+
+fn raw_url(api: &RepoApi, location: &RepoLocation, commit: &str, path: &str) -> String {
+    match location.provider {
+        // Raw files on github.com do not count against the API rate limit.
+        Provider::GitHub if location.host() == "github.com" => format!(
+            "https://raw.githubusercontent.com/{}/{}/{}",
+            location.repo_path,
+            commit,
+            encode_path(path)
+        ),
+        Provider::GitHub => format!(
+            "{}?ref={}",
+            api.github(&format!("/contents/{}", encode_path(path))),
+            commit
+        ),
+        Provider::GitLab => api.gitlab(&format!(
+            "/repository/files/{}/raw?ref={}",
+            encode_component(path),
+            commit
+        )),
+        _ => api.gitea(&format!("/raw/{}?ref={}", encode_path(path), commit)),
+    }
+}
+
+/// Writes at most `limit + 1` bytes of `reader` to `target`, so an oversized file is noticed
+/// without downloading all of it.
+fn copy_limited<R: Read>(reader: R, target: &Path, limit: u64) -> Result<u64> {
+    let mut writer = BufWriter::new(File::create(target)?);
+    let written = io::copy(&mut reader.take(limit.saturating_add(1)), &mut writer)?;
+    writer.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote;
+    use crate::test_support::{MockResponse, MockServer, TempDir};
+
+    const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn reports_files_left_out_for_size() {
+        let server = MockServer::start(|request| {
+            let api = "/api/v3/repos/o/r";
+            let path = request.path.as_str();
+            if path == format!("{}/commits/main", api) {
+                MockResponse::new(200, SHA)
+            } else if path == format!("{}/git/trees/{}?recursive=1", api, SHA) {
+                // `src/unsized.bin` has no size, so only its download shows it is too large.
+                MockResponse::new(
+                    200,
+                    r#"{"truncated":false,"tree":[
+                        {"path":"src/main.rs","type":"blob","mode":"100644","size":5},
+                        {"path":"src/big.bin","type":"blob","mode":"100644","size":50},
+                        {"path":"src/unsized.bin","type":"blob","mode":"100644"},
+                        {"path":"src/.hidden","type":"blob","mode":"100644","size":1},
+                        {"path":"docs/other.md","type":"blob","mode":"100644","size":1}
+                    ]}"#,
+                )
+            } else if path == format!("{}/contents/src/main.rs?ref={}", api, SHA) {
+                MockResponse::new(200, "hello")
+            } else if path == format!("{}/contents/src/unsized.bin?ref={}", api, SHA) {
+                MockResponse::new(200, vec![0u8; 50])
+            } else {
+                MockResponse::new(404, "")
+            }
+        });
+
+        let url = format!("{}/o/r/tree/main/src", server.base_url());
+        let location = remote::parse_repo_url(&url, None, Some(Provider::GitHub)).unwrap();
+        let options = DownloadOptions {
+            max_file_size: Some(10),
+            ..DownloadOptions::default()
+        };
+        let dest = TempDir::new();
+        let handle = DownloadHandle::register("sparse-test");
+
+        let fetched = fetch_subdirectory(
+            &Client::new(),
+            &location,
+            None,
+            dest.path(),
+            &options,
+            &handle,
+            |_| {},
+        )
+        .unwrap()
+        .expect("GitHub trees can be fetched sparsely");
+
+        assert_eq!(fetched.tree.commit.as_deref(), Some(SHA));
+        let src = fetched.tree.files.join("src");
+        assert_eq!(fs::read_to_string(src.join("main.rs")).unwrap(), "hello");
+        assert!(!src.join("big.bin").exists());
+        assert!(!src.join("unsized.bin").exists());
+        assert_eq!(fetched.too_large, ["big.bin", "unsized.bin"]);
+    }
+}
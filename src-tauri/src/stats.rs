@@ -0,0 +1,245 @@
+use crate::utils::FileMetadata;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const LARGEST_FILES_LIMIT: usize = 10;
+pub const UNKNOWN_LANGUAGE: &str = "Other";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParseStats {
+    pub languages: Vec<BreakdownEntry>,
+    pub extensions: Vec<BreakdownEntry>,
+    pub largest_files: Vec<LargestFile>,
+    pub total_lines: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BreakdownEntry {
+    pub name: String,
+    pub files: usize,
+    pub lines: usize,
+    pub bytes: u64,
+    pub tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LargestFile {
+    pub relative_path: String,
+    pub language: String,
+    pub size: u64,
+    pub tokens: usize,
+}
+
+pub fn compute_stats(files: &[FileMetadata]) -> ParseStats {
+    let mut languages: HashMap<&str, BreakdownEntry> = HashMap::new();
+    let mut extensions: HashMap<String, BreakdownEntry> = HashMap::new();
+
+    for file in files {
+        add_to_breakdown(languages.entry(&file.language).or_default(), file);
+        add_to_breakdown(
+            extensions.entry(extension_key(&file.name)).or_default(),
+            file,
+        );
+    }
+
+    let languages = finish_breakdown(languages.into_iter().map(|(k, v)| (k.to_string(), v)));
+    let extensions = finish_breakdown(extensions.into_iter());
+
+    let mut by_size: Vec<&FileMetadata> = files.iter().collect();
+    by_size.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+
+    let largest_files = by_size
+        .into_iter()
+        .take(LARGEST_FILES_LIMIT)
+        .map(|file| LargestFile {
+            relative_path: file.relative_path.clone(),
+            language: file.language.clone(),
+            size: file.size,
+            tokens: file.tokens,
+        })
+        .collect();
+
+    ParseStats {
+        languages,
+        extensions,
+        largest_files,
+        total_lines: files.iter().map(|f| f.lines).sum(),
+        total_tokens: files.iter().map(|f| f.tokens).sum(),
+    }
+}
+
+fn add_to_breakdown(entry: &mut BreakdownEntry, file: &FileMetadata) {
+    entry.files += 1;
+    entry.lines += file.lines;
+    entry.bytes += file.size;
+    entry.tokens += file.tokens;
+}
+
+/// Names each entry by its key and orders the breakdown by token count, largest first.
+fn finish_breakdown(
+    entries: impl Iterator<Item = (String, BreakdownEntry)>,
+) -> Vec<BreakdownEntry> {
+    let mut result: Vec<BreakdownEntry> = entries
+        .map(|(name, entry)| BreakdownEntry { name, ..entry })
+        .collect();
+
+    result.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.name.cmp(&b.name)));
+    result
+}
+
+fn extension_key(file_name: &str) -> String {
+    match Path::new(file_name).extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy().to_lowercase()),
+        None => "(none)".to_string(),
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Counting & Detection
+// /////////////////////////////////////////////////////////////////////////////
+
+pub fn count_lines(content: &str) -> usize {
+    if content.is_empty() {
+        return 0;
+    }
+    content.lines().count()
+}
+
+/// Rough token estimate (~4 characters per token), close enough to compare files and languages.
+pub fn estimate_tokens(content: &str) -> usize {
+    content.chars().count().div_ceil(4)
+}
+
+pub fn detect_language(path: &Path, content: &str) -> &'static str {
+    let file_name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    if let Some(language) = language_from_file_name(&file_name) {
+        return language;
+    }
+
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if let Some(language) = language_from_extension(&extension) {
+        return language;
+    }
+
+    content
+        .lines()
+        .next()
+        .and_then(language_from_shebang)
+        .unwrap_or(UNKNOWN_LANGUAGE)
+}
+
+fn language_from_file_name(file_name: &str) -> Option<&'static str> {
+    let language = match file_name {
+        "dockerfile" | "containerfile" => "Dockerfile",
+        "makefile" | "gnumakefile" => "Makefile",
+        "cmakelists.txt" => "CMake",
+        "gemfile" | "rakefile" => "Ruby",
+        "justfile" => "Just",
+        _ => return None,
+    };
+    Some(language)
+}
+
+fn language_from_extension(extension: &str) -> Option<&'static str> {
+    let language = match extension {
+        "rs" => "Rust",
+        "ts" | "mts" | "cts" => "TypeScript",
+        "tsx" => "TSX",
+        "js" | "mjs" | "cjs" => "JavaScript",
+        "jsx" => "JSX",
+        "svelte" => "Svelte",
+        "vue" => "Vue",
+        "py" | "pyi" => "Python",
+        "ipynb" => "Jupyter Notebook",
+        "go" => "Go",
+        "java" => "Java",
+        "kt" | "kts" => "Kotlin",
+        "scala" => "Scala",
+        "swift" => "Swift",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "C++",
+        "cs" => "C#",
+        "fs" | "fsx" => "F#",
+        "m" | "mm" => "Objective-C",
+        "rb" => "Ruby",
+        "php" => "PHP",
+        "lua" => "Lua",
+        "pl" | "pm" => "Perl",
+        "r" => "R",
+        "dart" => "Dart",
+        "ex" | "exs" => "Elixir",
+        "erl" | "hrl" => "Erlang",
+        "hs" => "Haskell",
+        "ml" | "mli" => "OCaml",
+        "clj" | "cljs" | "cljc" => "Clojure",
+        "zig" => "Zig",
+        "nim" => "Nim",
+        "sh" | "bash" | "zsh" | "fish" => "Shell",
+        "ps1" | "psm1" => "PowerShell",
+        "bat" | "cmd" => "Batch",
+        "sql" => "SQL",
+        "html" | "htm" => "HTML",
+        "css" => "CSS",
+        "scss" | "sass" => "SCSS",
+        "less" => "Less",
+        "json" | "jsonc" | "json5" => "JSON",
+        "yaml" | "yml" => "YAML",
+        "toml" => "TOML",
+        "xml" | "xsd" | "xsl" => "XML",
+        "md" | "markdown" | "mdx" => "Markdown",
+        "rst" => "reStructuredText",
+        "txt" => "Text",
+        "csv" | "tsv" => "CSV",
+        "proto" => "Protocol Buffers",
+        "graphql" | "gql" => "GraphQL",
+        "tf" | "hcl" => "HCL",
+        "nix" => "Nix",
+        "gradle" => "Gradle",
+        "cmake" => "CMake",
+        "mk" => "Makefile",
+        _ => return None,
+    };
+    Some(language)
+}
+
+fn language_from_shebang(first_line: &str) -> Option<&'static str> {
+    let interpreter_line = first_line.strip_prefix("#!")?;
+    let mut parts = interpreter_line.split_whitespace();
+    let mut program = parts.next()?.rsplit('/').next()?;
+
+    // `#!/usr/bin/env python3` names the real interpreter in the next argument.
+    if program == "env" {
+        program = parts.find(|arg| !arg.starts_with('-'))?;
+    }
+
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    let language = match program {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish" => "Shell",
+        "python" => "Python",
+        "node" | "deno" | "bun" => "JavaScript",
+        "ruby" => "Ruby",
+        "perl" => "Perl",
+        "php" => "PHP",
+        "lua" => "Lua",
+        "Rscript" => "R",
+        "pwsh" => "PowerShell",
+        _ => return None,
+    };
+    Some(language)
+}
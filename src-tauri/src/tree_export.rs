@@ -0,0 +1,314 @@
+use crate::utils::ParsedPath;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TreeFormat {
+    Text,
+    Markdown,
+    Json,
+    Dot,
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TreeExportOptions {
+    /// Deepest level rendered below the roots (roots are depth 0). `None` renders everything.
+    pub max_depth: Option<usize>,
+    pub show_sizes: bool,
+}
+
+pub fn render_tree(
+    tree: &[ParsedPath],
+    format: TreeFormat,
+    options: &TreeExportOptions,
+) -> Result<String> {
+    let roots = sorted(tree);
+
+    let output = match format {
+        TreeFormat::Text => render_text(&roots, options),
+        TreeFormat::Markdown => render_markdown(&roots, options),
+        TreeFormat::Json => render_json(&roots, options)?,
+        TreeFormat::Dot => render_dot(&roots, options),
+        TreeFormat::Mermaid => render_mermaid(&roots, options),
+    };
+
+    Ok(output)
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Renderers
+// /////////////////////////////////////////////////////////////////////////////
+
+fn render_text(roots: &[&ParsedPath], options: &TreeExportOptions) -> String {
+    let mut out = String::new();
+    for root in roots {
+        let _ = writeln!(out, "{}", label(root, options));
+        write_text_children(&mut out, root, "", 0, options);
+    }
+    out
+}
+
+fn write_text_children(
+    out: &mut String,
+    node: &ParsedPath,
+    prefix: &str,
+    depth: usize,
+    options: &TreeExportOptions,
+) {
+    let children = visible_children(node, depth, options);
+    let count = children.len();
+
+    for (i, child) in children.into_iter().enumerate() {
+        let is_last = i + 1 == count;
+        let (branch, indent) = if is_last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        let _ = writeln!(out, "{}{}{}", prefix, branch, label(child, options));
+        write_text_children(
+            out,
+            child,
+            &format!("{}{}", prefix, indent),
+            depth + 1,
+            options,
+        );
+    }
+}
+
+fn render_markdown(roots: &[&ParsedPath], options: &TreeExportOptions) -> String {
+    let mut out = String::new();
+    for root in roots {
+        write_markdown_node(&mut out, root, 0, options);
+    }
+    out
+}
+
+fn write_markdown_node(
+    out: &mut String,
+    node: &ParsedPath,
+    depth: usize,
+    options: &TreeExportOptions,
+) {
+    let _ = write!(out, "{}- `{}", "  ".repeat(depth), node_name(node));
+    if is_dir(node) {
+        out.push('/');
+    }
+    out.push('`');
+    if options.show_sizes {
+        let _ = write!(out, " ({})", format_size(node_size(node)));
+    }
+    out.push('\n');
+    for child in visible_children(node, depth, options) {
+        write_markdown_node(out, child, depth + 1, options);
+    }
+}
+
+fn render_json(roots: &[&ParsedPath], options: &TreeExportOptions) -> Result<String> {
+    let nodes: Vec<Value> = roots
+        .iter()
+        .map(|root| json_node(root, 0, options))
+        .collect();
+    Ok(serde_json::to_string_pretty(&nodes)?)
+}
+
+fn json_node(node: &ParsedPath, depth: usize, options: &TreeExportOptions) -> Value {
+    let mut value = match node {
+        ParsedPath::File {
+            name,
+            relative_path,
+            ..
+        } => json!({
+            "type": "file",
+            "name": name,
+            "relative_path": relative_path,
+        }),
+        ParsedPath::Directory {
+            name,
+            relative_path,
+            ..
+        } => json!({
+            "type": "directory",
+            "name": name,
+            "relative_path": relative_path,
+            "children": visible_children(node, depth, options)
+                .into_iter()
+                .map(|child| json_node(child, depth + 1, options))
+                .collect::<Vec<_>>(),
+        }),
+    };
+
+    if options.show_sizes {
+        value["size"] = json!(node_size(node));
+    }
+
+    value
+}
+
+fn render_dot(roots: &[&ParsedPath], options: &TreeExportOptions) -> String {
+    let mut out = String::from("digraph tree {\n    rankdir=LR;\n    node [shape=box];\n");
+    let mut next_id = 0;
+    for root in roots {
+        write_graph_node(
+            &mut out,
+            root,
+            None,
+            0,
+            &mut next_id,
+            GraphSyntax::Dot,
+            options,
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid(roots: &[&ParsedPath], options: &TreeExportOptions) -> String {
+    let mut out = String::from("graph TD\n");
+    let mut next_id = 0;
+    for root in roots {
+        write_graph_node(
+            &mut out,
+            root,
+            None,
+            0,
+            &mut next_id,
+            GraphSyntax::Mermaid,
+            options,
+        );
+    }
+    out
+}
+
+#[derive(Clone, Copy)]
+enum GraphSyntax {
+    Dot,
+    Mermaid,
+}
+
+fn write_graph_node(
+    out: &mut String,
+    node: &ParsedPath,
+    parent: Option<usize>,
+    depth: usize,
+    next_id: &mut usize,
+    syntax: GraphSyntax,
+    options: &TreeExportOptions,
+) {
+    let id = *next_id;
+    *next_id += 1;
+
+    let text = label(node, options);
+    match syntax {
+        GraphSyntax::Dot => {
+            let shape = if is_dir(node) { "folder" } else { "box" };
+            let _ = writeln!(
+                out,
+                "    n{} [label=\"{}\", shape={}];",
+                id,
+                escape_dot(&text),
+                shape
+            );
+            if let Some(parent_id) = parent {
+                let _ = writeln!(out, "    n{} -> n{};", parent_id, id);
+            }
+        }
+        GraphSyntax::Mermaid => {
+            let _ = writeln!(out, "    n{}[\"{}\"]", id, escape_mermaid(&text));
+            if let Some(parent_id) = parent {
+                let _ = writeln!(out, "    n{} --> n{}", parent_id, id);
+            }
+        }
+    }
+
+    for child in visible_children(node, depth, options) {
+        write_graph_node(out, child, Some(id), depth + 1, next_id, syntax, options);
+    }
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Helpers
+// /////////////////////////////////////////////////////////////////////////////
+
+fn sorted(nodes: &[ParsedPath]) -> Vec<&ParsedPath> {
+    let mut nodes: Vec<&ParsedPath> = nodes.iter().collect();
+    nodes.sort_by(|a, b| match (is_dir(a), is_dir(b)) {
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        _ => node_name(a).cmp(node_name(b)),
+    });
+    nodes
+}
+
+fn visible_children<'a>(
+    node: &'a ParsedPath,
+    depth: usize,
+    options: &TreeExportOptions,
+) -> Vec<&'a ParsedPath> {
+    if options.max_depth.is_some_and(|max| depth >= max) {
+        return Vec::new();
+    }
+
+    match node {
+        ParsedPath::Directory { children, .. } => sorted(children),
+        ParsedPath::File { .. } => Vec::new(),
+    }
+}
+
+fn label(node: &ParsedPath, options: &TreeExportOptions) -> String {
+    let mut label = node_name(node).to_string();
+    if is_dir(node) {
+        label.push('/');
+    }
+    if options.show_sizes {
+        let _ = write!(label, " ({})", format_size(node_size(node)));
+    }
+    label
+}
+
+fn node_name(node: &ParsedPath) -> &str {
+    match node {
+        ParsedPath::File { name, .. } | ParsedPath::Directory { name, .. } => name,
+    }
+}
+
+fn node_size(node: &ParsedPath) -> u64 {
+    match node {
+        ParsedPath::File { size, .. } | ParsedPath::Directory { size, .. } => *size,
+    }
+}
+
+fn is_dir(node: &ParsedPath) -> bool {
+    matches!(node, ParsedPath::Directory { .. })
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn escape_dot(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(input: &str) -> String {
+    input.replace('"', "#quot;")
+}
@@ -0,0 +1,436 @@
+use crate::encoding;
+use crate::tree_export::format_size;
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read},
+    path::Path,
+};
+
+/// What to do with a file larger than `ParseOptions::max_file_size`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum OversizeStrategy {
+    #[default]
+    Skip,
+    Head {
+        lines: usize,
+    },
+    HeadTail {
+        head: usize,
+        tail: usize,
+    },
+    /// Evenly spaced rows for CSV/TSV/JSONL data files (CSV header kept); head lines for anything else.
+    Sample {
+        rows: usize,
+    },
+}
+
+pub enum FileBody {
+    Full(String),
+    /// Partial content with the explanatory markers already inlined.
+    Truncated(String),
+    Skipped(String),
+}
+
+/// Source encoding of the file being cut, so each kept line can be transcoded on its own.
+#[derive(Clone, Copy)]
+pub struct SourceEncoding {
+    pub encoding: &'static Encoding,
+    pub bom_len: usize,
+}
+
+/// Where the lines being cut are read from.
+#[derive(Clone, Copy)]
+enum Input<'a> {
+    File(&'a Path, SourceEncoding),
+    /// Text already extracted from the file, e.g. a notebook's cells.
+    Text(&'a str),
+}
+
+pub fn read_oversized(
+    path: &Path,
+    size: u64,
+    max_size: u64,
+    strategy: &OversizeStrategy,
+    source: SourceEncoding,
+) -> Result<FileBody> {
+    cut_oversized(path, Input::File(path, source), size, max_size, strategy)
+}
+
+/// Like `read_oversized`, for text extracted from `path` that went over the limit.
+pub fn cut_text(
+    path: &Path,
+    text: &str,
+    max_size: u64,
+    strategy: &OversizeStrategy,
+) -> Result<FileBody> {
+    cut_oversized(
+        path,
+        Input::Text(text),
+        text.len() as u64,
+        max_size,
+        strategy,
+    )
+}
+
+fn cut_oversized(
+    path: &Path,
+    input: Input,
+    size: u64,
+    max_size: u64,
+    strategy: &OversizeStrategy,
+) -> Result<FileBody> {
+    let limit_note = format!(
+        "file is {}, limit {}",
+        format_size(size),
+        format_size(max_size)
+    );
+
+    let body = match strategy {
+        OversizeStrategy::Skip => {
+            FileBody::Skipped(marker(&format!("content skipped: {}", limit_note)))
+        }
+        OversizeStrategy::Head { lines } => read_head_tail(input, *lines, 0, &limit_note)?,
+        OversizeStrategy::HeadTail { head, tail } => {
+            read_head_tail(input, *head, *tail, &limit_note)?
+        }
+        OversizeStrategy::Sample { rows } => {
+            if is_row_data_file(path) {
+                read_sample(input, has_header_row(path), *rows, &limit_note)?
+            } else {
+                read_head_tail(input, *rows, 0, &limit_note)?
+            }
+        }
+    };
+
+    Ok(match body {
+        FileBody::Truncated(content) => FileBody::Truncated(clip_to_size(content, max_size)),
+        // Few enough lines to keep them all, e.g. a minified bundle: the file is still over the
+        // limit, so it is clipped in bytes or at least marked as oversized.
+        FileBody::Full(content) if content.len() as u64 > max_size => {
+            FileBody::Truncated(clip_to_size(content, max_size))
+        }
+        FileBody::Full(mut content) => {
+            ensure_trailing_newline(&mut content);
+            content.push_str(&marker(&format!("kept whole: {}", limit_note)));
+            FileBody::Truncated(content)
+        }
+        skipped @ FileBody::Skipped(_) => skipped,
+    })
+}
+
+fn read_head_tail(input: Input, head: usize, tail: usize, limit_note: &str) -> Result<FileBody> {
+    let mut reader = LineReader::open(input)?;
+    let mut head_lines = Vec::with_capacity(head);
+    let mut tail_lines: VecDeque<String> = VecDeque::with_capacity(tail);
+    let mut total = 0usize;
+
+    while let Some(line) = reader.next_line()? {
+        if total < head {
+            head_lines.push(line);
+        } else if tail > 0 {
+            if tail_lines.len() == tail {
+                tail_lines.pop_front();
+            }
+            tail_lines.push_back(line);
+        }
+        total += 1;
+    }
+
+    let omitted = total - head_lines.len() - tail_lines.len();
+    if omitted == 0 {
+        let mut content = head_lines.concat();
+        content.extend(tail_lines);
+        return Ok(FileBody::Full(content));
+    }
+
+    let mut content = head_lines.concat();
+    ensure_trailing_newline(&mut content);
+
+    if tail_lines.is_empty() {
+        content.push_str(&marker(&format!(
+            "truncated: kept first {} of {} lines; {}",
+            head_lines.len(),
+            total,
+            limit_note
+        )));
+    } else {
+        content.push_str(&marker(&format!(
+            "truncated: {} lines omitted, kept first {} and last {} of {} lines; {}",
+            omitted,
+            head_lines.len(),
+            tail_lines.len(),
+            total,
+            limit_note
+        )));
+        content.extend(tail_lines);
+    }
+
+    Ok(FileBody::Truncated(content))
+}
+
+fn read_sample(input: Input, has_header: bool, rows: usize, limit_note: &str) -> Result<FileBody> {
+    let mut total = 0usize;
+    let mut reader = LineReader::open(input)?;
+    while reader.next_line()?.is_some() {
+        total += 1;
+    }
+
+    let data_rows = total.saturating_sub(has_header as usize);
+    if data_rows <= rows {
+        return read_head_tail(input, total, 0, limit_note);
+    }
+
+    // Index of the first data row of each of the `rows` equal-width buckets.
+    let picks: Vec<usize> = (0..rows).map(|i| i * data_rows / rows.max(1)).collect();
+
+    let mut sampled = String::new();
+    let mut next_pick = picks.iter().peekable();
+    let mut reader = LineReader::open(input)?;
+    let mut index = 0usize;
+
+    while let Some(line) = reader.next_line()? {
+        if has_header && index == 0 {
+            sampled.push_str(&line);
+            ensure_trailing_newline(&mut sampled);
+            sampled.push_str(&marker(&format!(
+                "sampled: {} of {} rows, evenly spaced; {}",
+                rows, data_rows, limit_note
+            )));
+        } else {
+            let row = index - has_header as usize;
+            if next_pick.peek().is_some_and(|&&pick| pick == row) {
+                next_pick.next();
+                sampled.push_str(&line);
+            }
+            if next_pick.peek().is_none() {
+                break;
+            }
+        }
+        index += 1;
+    }
+
+    if !has_header {
+        sampled.insert_str(
+            0,
+            &marker(&format!(
+                "sampled: {} of {} rows, evenly spaced; {}",
+                rows, data_rows, limit_note
+            )),
+        );
+    }
+
+    Ok(FileBody::Truncated(sampled))
+}
+
+// /////////////////////////////////////////////////////////////////////////////
+// Helpers
+// /////////////////////////////////////////////////////////////////////////////
+
+pub fn marker(message: &str) -> String {
+    format!("... [{}] ...\n", message)
+}
+
+struct LineReader<'a> {
+    reader: Box<dyn BufRead + 'a>,
+    encoding: &'static Encoding,
+}
+
+impl<'a> LineReader<'a> {
+    fn open(input: Input<'a>) -> Result<Self> {
+        let (path, source) = match input {
+            Input::File(path, source) => (path, source),
+            Input::Text(text) => {
+                return Ok(Self {
+                    reader: Box::new(Cursor::new(text.as_bytes())),
+                    encoding: encoding_rs::UTF_8,
+                })
+            }
+        };
+        let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut bom = vec![0u8; source.bom_len];
+        reader.read_exact(&mut bom)?;
+
+        Ok(Self {
+            reader: Box::new(reader),
+            encoding: source.encoding,
+        })
+    }
+
+    /// Reads one line including its terminator and transcodes it to UTF-8.
+    fn next_line(&mut self) -> Result<Option<String>> {
+        let mut buf = Vec::new();
+        if self.reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(None);
+        }
+
+        // In UTF-16 the newline is two bytes; keep code units paired across line breaks.
+        if encoding::is_utf16(self.encoding) && buf.len() % 2 == 1 {
+            let mut next = [0u8; 1];
+            if self.reader.read(&mut next)? == 1 {
+                buf.push(next[0]);
+            }
+        }
+
+        let (line, _) = self.encoding.decode_without_bom_handling(&buf);
+        Ok(Some(line.into_owned()))
+    }
+}
+
+fn ensure_trailing_newline(content: &mut String) {
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+}
+
+/// Minified files keep the line count tiny, so the kept part is also capped in bytes.
+fn clip_to_size(mut content: String, max_size: u64) -> String {
+    let max_size = usize::try_from(max_size).unwrap_or(usize::MAX);
+    if content.len() <= max_size {
+        return content;
+    }
+
+    let mut cut = max_size;
+    while !content.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    content.truncate(cut);
+    ensure_trailing_newline(&mut content);
+    content.push_str(&marker(&format!(
+        "truncated: kept content clipped to {}",
+        format_size(max_size as u64)
+    )));
+    content
+}
+
+fn file_extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_row_data_file(path: &Path) -> bool {
+    matches!(
+        file_extension(path).as_str(),
+        "csv" | "tsv" | "jsonl" | "ndjson"
+    )
+}
+
+fn has_header_row(path: &Path) -> bool {
+    matches!(file_extension(path).as_str(), "csv" | "tsv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{write_file, TempDir};
+
+    const UTF8: SourceEncoding = SourceEncoding {
+        encoding: encoding_rs::UTF_8,
+        bom_len: 0,
+    };
+
+    fn cut(
+        dir: &TempDir,
+        name: &str,
+        content: &str,
+        max: u64,
+        strategy: OversizeStrategy,
+    ) -> String {
+        write_file(dir.path(), name, content);
+        let path = dir.path().join(name);
+        match read_oversized(&path, content.len() as u64, max, &strategy, UTF8).unwrap() {
+            FileBody::Truncated(body) => body,
+            FileBody::Full(_) => panic!("an oversized file was kept without a marker"),
+            FileBody::Skipped(note) => panic!("unexpectedly skipped: {}", note),
+        }
+    }
+
+    fn numbered(lines: usize) -> String {
+        (1..=lines).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn head_keeps_the_first_lines() {
+        let dir = TempDir::new();
+        let body = cut(
+            &dir,
+            "a.txt",
+            &numbered(10),
+            1000,
+            OversizeStrategy::Head { lines: 2 },
+        );
+
+        assert!(body.starts_with("line 1\nline 2\n... [truncated: kept first 2 of 10 lines;"));
+        assert!(!body.contains("line 3"));
+    }
+
+    #[test]
+    fn head_tail_keeps_both_ends() {
+        let dir = TempDir::new();
+        let strategy = OversizeStrategy::HeadTail { head: 1, tail: 2 };
+        let body = cut(&dir, "a.txt", &numbered(10), 1000, strategy);
+
+        assert!(body.starts_with("line 1\n... [truncated: 7 lines omitted"));
+        assert!(body.ends_with("line 9\nline 10\n"));
+    }
+
+    #[test]
+    fn sample_spreads_rows_and_keeps_the_header() {
+        let dir = TempDir::new();
+        let csv = format!(
+            "id\n{}",
+            (0..10).map(|i| format!("{}\n", i)).collect::<String>()
+        );
+        let body = cut(
+            &dir,
+            "data.csv",
+            &csv,
+            1000,
+            OversizeStrategy::Sample { rows: 5 },
+        );
+
+        let rows: Vec<&str> = body
+            .lines()
+            .filter(|line| !line.starts_with("..."))
+            .collect();
+        assert_eq!(rows, ["id", "0", "2", "4", "6", "8"]);
+        assert!(body.contains("[sampled: 5 of 10 rows"));
+    }
+
+    #[test]
+    fn clips_a_single_huge_line() {
+        let dir = TempDir::new();
+        let line = "x".repeat(1000);
+        for strategy in [
+            OversizeStrategy::Head { lines: 5 },
+            OversizeStrategy::HeadTail { head: 5, tail: 5 },
+            OversizeStrategy::Sample { rows: 5 },
+        ] {
+            let body = cut(&dir, "bundle.min.js", &line, 100, strategy);
+
+            assert!(body.starts_with(&"x".repeat(100)));
+            assert!(body.ends_with("... [truncated: kept content clipped to 100 B] ...\n"));
+        }
+    }
+
+    #[test]
+    fn marks_rows_that_all_fit() {
+        let dir = TempDir::new();
+        // Transcoding can leave a file under the limit after all its rows were kept.
+        let body = cut(
+            &dir,
+            "data.jsonl",
+            "{}\n{}\n",
+            10,
+            OversizeStrategy::Sample { rows: 5 },
+        );
+
+        assert!(body.starts_with("{}\n{}\n... [kept whole: file is"));
+    }
+}
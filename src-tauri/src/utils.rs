@@ -1,23 +1,41 @@
+use crate::archive::{self, ExtractLimits};
+use crate::attributes::LinguistAttributes;
+use crate::cache::{self, FetchedTree, RepoCache, DEFAULT_CACHE_MAX_SIZE};
+use crate::credentials;
+use crate::download::{self, DownloadHandle, DownloadOptions};
+use crate::encoding::{self, DecodedText, TranscodedFile};
+use crate::exclusions::{self, Exclusions};
+use crate::git::{self, ChangedFile, Changes, WorktreeFiles, WorktreeState};
+use crate::notebook;
+use crate::pull_request;
+use crate::remote::{self, Provider, RemoteSource, RepoLocation};
+use crate::repo_info::{self, RepoInfo};
+use crate::skip_report::{self, SkipReason, SkipReport, SkipSummary};
+use crate::sparse;
+use crate::stats::{self, ParseStats};
+use crate::tree_export::format_size;
+use crate::truncate::{self, FileBody, OversizeStrategy, SourceEncoding};
 use anyhow::{self, Context, Result};
 use chrono::{DateTime, Local};
 use content_inspector::{inspect, ContentType};
 use dirs;
+use reqwest;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
     collections::HashSet,
     fs::{self, File},
-    io::{self, Read, Write, Cursor},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     process::Command,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 use tauri::image::Image;
 use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
-use tauri::{include_image, App, Runtime, AppHandle, Emitter, Manager};
+use tauri::{include_image, App, AppHandle, Emitter, Manager, Runtime};
 use uuid::Uuid;
-use reqwest;
-use zip;
 
 pub const APP_NAME: &str = "parser-ai";
 pub const PARSED_FILES_DIR: &str = "parsed-files";
@@ -25,14 +43,28 @@ pub const CONTENT_FILENAME: &str = "content.txt";
 pub const METADATA_FILENAME: &str = "metadata.json";
 pub const TREE_FILENAME: &str = "tree.json";
 pub const TEMP_REPOS_DIR: &str = "temp-repos";
+pub const REPO_CACHE_DIR: &str = "repo-cache";
+/// Fetched repositories not parsed within this time are treated as abandoned.
+pub const TEMP_REPO_MAX_AGE: Duration = Duration::from_secs(6 * 60 * 60);
 pub const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+pub const ENCODING_SAMPLE_SIZE: u64 = 64 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMetadata {
     pub path: String,
     pub name: String,
     pub size: u64,
-    pub relative_path: String
+    pub relative_path: String,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub lines: usize,
+    #[serde(default)]
+    pub tokens: usize,
+    #[serde(default)]
+    pub encoding: String,
+    #[serde(default)]
+    pub had_bom: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +77,148 @@ pub struct ParseMetadata {
     pub files_count: usize,
     pub total_size: u64,
     pub remote_url: String,
+    #[serde(default)]
+    pub stats: ParseStats,
+    #[serde(default)]
+    pub skipped: SkipSummary,
+    /// Files that were not plain UTF-8 and were converted for `content.txt`.
+    #[serde(default)]
+    pub transcoded_files: Vec<TranscodedFile>,
+    /// Branch, tag or commit requested for a remote parse. Unset for a combined parse of
+    /// several sources, which records them per source.
+    #[serde(default)]
+    pub git_ref: Option<String>,
+    #[serde(default)]
+    pub commit_sha: Option<String>,
+    /// Description, license and the like from the hosting service of a remote parse.
+    #[serde(default)]
+    pub repository: Option<RepoInfo>,
+    /// The sources of a combined parse, in the order they were given.
+    #[serde(default)]
+    pub sources: Vec<SourceSummary>,
+    /// Git state of the working tree a local parse was taken from; unset when the parsed paths
+    /// span several, see `SourceSummary::worktree`.
+    #[serde(default)]
+    pub worktree: Option<WorktreeState>,
+    /// What a parse restricted by `ParseOptions::changed_since` compared against.
+    #[serde(default)]
+    pub changes: Option<ChangeSummary>,
+}
+
+/// The base of a changes-only parse and the files that differed from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeSummary {
+    pub base_ref: String,
+    pub base_commit: String,
+    pub files: Vec<ChangedFile>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchMethod {
+    /// GitHub archive download for `https://github.com/...`, git clone for everything else.
+    #[default]
+    Auto,
+    Archive,
+    Git,
+}
+
+/// One input of a combined parse: a repository to fetch or a local file or directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceInput {
+    Local {
+        path: String,
+    },
+    Remote {
+        url: String,
+        #[serde(default, rename = "ref")]
+        reference: Option<String>,
+        #[serde(default)]
+        method: FetchMethod,
+        #[serde(default)]
+        provider: Option<Provider>,
+    },
+}
+
+/// Where one source of a combined parse came from and the top-level name its files are under.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceSummary {
+    pub name: String,
+    /// Set for local sources.
+    pub path: Option<String>,
+    /// Set for fetched repositories.
+    pub url: Option<String>,
+    pub git_ref: Option<String>,
+    pub commit_sha: Option<String>,
+    /// Git state of a local source inside a working tree.
+    #[serde(default)]
+    pub worktree: Option<WorktreeState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ParseOptions {
+    /// Files above this size are handled by `oversize_strategy` instead of being written whole.
+    pub max_file_size: u64,
+    pub oversize_strategy: OversizeStrategy,
+    /// Write `.ipynb` files as their cells instead of raw JSON.
+    pub extract_notebooks: bool,
+    /// Also keep text outputs of notebook code cells (images are always dropped).
+    pub notebook_outputs: bool,
+    /// Start `content.txt` of a remote parse with what is known about the repository.
+    pub repository_header: bool,
+    /// In git working trees, take only the files in the git index, so untracked build output
+    /// never shows up.
+    pub tracked_only: bool,
+    /// In git working trees, take only the files added, modified or renamed since this branch,
+    /// tag or commit, uncommitted changes included.
+    pub changed_since: Option<String>,
+    /// With `changed_since`, follow each changed file with its unified diff against the base.
+    pub include_diffs: bool,
+    /// End `content.txt` with the staged and unstaged changes of each git working tree parsed.
+    pub uncommitted_diff: bool,
+    /// Commits listed in a history section per git repository, together with the commit that
+    /// last changed each parsed file. 0 leaves the section out.
+    pub history_commits: usize,
+    /// Leave out files `.gitattributes` marks `linguist-generated` or `linguist-vendored`.
+    pub linguist_attributes: bool,
+    /// Exclusion patterns for this parse instead of the saved list; empty excludes nothing.
+    pub exclusions: Option<Vec<String>>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: MAX_FILE_SIZE,
+            oversize_strategy: OversizeStrategy::default(),
+            extract_notebooks: true,
+            notebook_outputs: false,
+            repository_header: true,
+            tracked_only: false,
+            changed_since: None,
+            include_diffs: false,
+            uncommitted_diff: false,
+            history_commits: 0,
+            linguist_attributes: true,
+            exclusions: None,
+        }
+    }
+}
+
+/// Text written to `content.txt` around the parsed files, under its own `===== title =====`
+/// header.
+#[derive(Debug, Clone)]
+pub struct ContentSection {
+    pub title: String,
+    pub body: String,
+}
+
+/// Sections written before and after the files of a parse.
+#[derive(Debug, Clone, Default)]
+pub struct ExtraSections {
+    pub leading: Vec<ContentSection>,
+    pub trailing: Vec<ContentSection>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,6 +261,11 @@ pub fn init_app_structure() -> Result<()> {
     if !parsed_dir.exists() {
         fs::create_dir(&parsed_dir)?;
     }
+
+    // Anything left from a previous run that crashed or was never parsed.
+    if let Err(e) = remove_stale_temp_repos(TEMP_REPO_MAX_AGE) {
+        eprintln!("Failed to clean up old temp repos: {}", e);
+    }
     Ok(())
 }
 
@@ -130,44 +309,540 @@ fn create_parse_directory(remote_url: &str) -> Result<(PathBuf, File, String)> {
 // Git & Remote Handling
 // /////////////////////////////////////////////////////////////////////////////
 
-pub fn download_github_repo(url: &str) -> Result<PathBuf> {
-    let app_dir = get_app_dir()?;
-    let temp_dir = app_dir.join(TEMP_REPOS_DIR);
-    if !temp_dir.exists() {
-        fs::create_dir_all(&temp_dir)?;
+/// Fetches a repository (or one revision/subdirectory of it) into `temp-repos/<id>/` and returns
+/// the directory to parse. Where the files came from is recorded in `source.json` next to them.
+/// With the cache enabled the files are linked from `repo-cache/`, which also serves them when
+/// the remote is unreachable.
+pub fn fetch_repository(
+    url: &str,
+    reference: Option<&str>,
+    method: FetchMethod,
+    provider: Option<Provider>,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: &AppHandle,
+) -> Result<PathBuf> {
+    let location = remote::parse_repo_url(url, reference, provider)?;
+
+    let use_git = match method {
+        FetchMethod::Archive => false,
+        FetchMethod::Git => true,
+        FetchMethod::Auto => location.provider == Provider::Git,
+    };
+
+    if let Err(e) = remove_stale_temp_repos(TEMP_REPO_MAX_AGE) {
+        eprintln!("Failed to clean up old temp repos: {}", e);
     }
 
-    let clean_url = url.trim_end_matches('/').trim_end_matches(".git");
-    let archive_url = format!("{}/archive/HEAD.zip", clean_url);
+    let repo_root = get_app_dir()?
+        .join(TEMP_REPOS_DIR)
+        .join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&repo_root)?;
 
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("parser-ai-app")
-        .build()?;
+    let result = fetch_into(&location, &repo_root, use_git, options, handle, app);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&repo_root);
+    }
+    result
+}
 
-    let response = client.get(&archive_url).send()?;
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("Failed to download repository: HTTP {}", response.status()));
+fn fetch_into(
+    location: &RepoLocation,
+    repo_root: &Path,
+    use_git: bool,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: &AppHandle,
+) -> Result<PathBuf> {
+    let sparse = match location.subdir {
+        Some(_) if options.sparse_subdir && !use_git => {
+            fetch_sparse(location, repo_root, options, handle, app)?
+        }
+        _ => None,
+    };
+    let too_large = sparse
+        .as_ref()
+        .map(|fetched| fetched.too_large.clone())
+        .unwrap_or_default();
+
+    // Whether the files are what the remote serves right now, rather than a cached copy kept
+    // because fetching failed.
+    let (tree, fresh) = if let Some(fetched) = sparse {
+        (fetched.tree, true)
+    } else if options.use_cache {
+        let cache = RepoCache::new(get_app_dir()?.join(REPO_CACHE_DIR), DEFAULT_CACHE_MAX_SIZE);
+        let (cached, fresh) = fetch_through_cache(&cache, location, use_git, options, handle, app)?;
+
+        let name = location.name();
+        let files = repo_root.join(&name);
+        cache::link_tree(&cached.files, &files)?;
+        let git_dir = match &cached.git_dir {
+            Some(git_dir) => {
+                let linked = repo_root.join(format!("{}.git", name));
+                cache::link_tree(git_dir, &linked)?;
+                Some(linked)
+            }
+            None => None,
+        };
+        (
+            FetchedTree {
+                files,
+                commit: cached.commit,
+                git_dir,
+            },
+            fresh,
+        )
+    } else if use_git {
+        (clone_remote(location, repo_root, options, handle)?, true)
+    } else {
+        let (tree, _) = download_repo_archive(location, repo_root, None, options, handle, app)?
+            .context("Server answered 304 Not Modified to an unconditional request")?;
+        (tree, true)
+    };
+
+    let path = match location.subdir.as_deref() {
+        Some(subdir) => {
+            let subdir_path = tree.files.join(remote::safe_subdir(subdir)?);
+            if !subdir_path.is_dir() {
+                return Err(anyhow::anyhow!(
+                    "Path '{}' not found in {}",
+                    subdir,
+                    location.url
+                ));
+            }
+            subdir_path
+        }
+        None => tree.files,
+    };
+
+    let (commit, repository) = describe_repository(location, tree.commit, fresh, options);
+    let source = RemoteSource {
+        url: location.url.clone(),
+        reference: location.reference.clone(),
+        commit,
+        subdir: location.subdir.clone(),
+        git_dir: tree.git_dir.map(|dir| dir.to_string_lossy().to_string()),
+        repository,
+        too_large,
+    };
+    source.save(repo_root)?;
+    Ok(path)
+}
+
+/// Asks the hosting API about `location`, and for the commit when the download did not tell.
+/// The ref is only resolved for `fresh` files: a stale cached copy may be older than what the
+/// ref points at now, and a wrong commit is worse than none. The parse goes ahead without these
+/// when the API is unreachable, e.g. on a cached copy offline.
+fn describe_repository(
+    location: &RepoLocation,
+    commit: Option<String>,
+    fresh: bool,
+    options: &DownloadOptions,
+) -> (Option<String>, Option<RepoInfo>) {
+    let lookup = || -> Result<(Option<String>, Option<RepoInfo>)> {
+        let client = download::http_client(options)?;
+        let token = credentials::token_for(&get_app_dir()?, location.host());
+
+        let info = repo_info::fetch_repo_info(&client, location, token.as_deref())?;
+        let commit = match commit.clone() {
+            Some(commit) => Some(commit),
+            None if !fresh => None,
+            None => repo_info::resolve_commit(
+                &client,
+                location,
+                location.reference.as_deref(),
+                token.as_deref(),
+            )?,
+        };
+        Ok((commit, info))
+    };
+
+    match lookup() {
+        Ok(described) => described,
+        Err(e) => {
+            eprintln!("Failed to look up {} on its host: {:#}", location.url, e);
+            (commit, None)
+        }
     }
+}
 
-    let content = response.bytes()?;
+/// Fetches only the subdirectory of `location` through the hosting API, or returns `None` when
+/// the host cannot list it and the whole archive is needed.
+fn fetch_sparse(
+    location: &RepoLocation,
+    repo_root: &Path,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: &AppHandle,
+) -> Result<Option<sparse::SparseFetch>> {
+    let client = download::http_client(options)?;
+    let token = credentials::token_for(&get_app_dir()?, location.host());
+    sparse::fetch_subdirectory(
+        &client,
+        location,
+        token.as_deref(),
+        repo_root,
+        options,
+        handle,
+        |progress| {
+            let _ = app.emit("download-progress", progress);
+        },
+    )
+}
 
-    let reader = Cursor::new(content);
-    let mut archive = zip::ZipArchive::new(reader)?;
+/// Serves `location` from the cache when its commit is already there, revalidates refs with the
+/// stored ETag, and falls back to the last cached copy of the ref when fetching fails. The flag
+/// is false for that fallback, whose files may be older than the ref.
+fn fetch_through_cache(
+    cache: &RepoCache,
+    location: &RepoLocation,
+    use_git: bool,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: &AppHandle,
+) -> Result<(FetchedTree, bool)> {
+    let reference = location.reference.as_deref().unwrap_or("HEAD");
+    // Clones are only reused when they reach as far back as this fetch would.
+    let depth = use_git.then_some(options.clone_depth);
+
+    // A full SHA always names the same files, so there is nothing to revalidate.
+    if reference.len() == 40 && git::looks_like_commit_sha(reference) {
+        if let Some(tree) = cache.lookup_commit(location, reference, depth) {
+            return Ok((tree, true));
+        }
+    }
 
-    let repo_id = Uuid::new_v4().to_string();
-    let extract_root = temp_dir.join(&repo_id);
+    let known = cache.lookup_ref(location, reference);
+    let staging = cache.staging_dir()?;
 
-    archive.extract(&extract_root)?;
+    let fetched = if use_git {
+        clone_remote(location, &staging, options, handle).map(|tree| Some((tree, None)))
+    } else {
+        let etag = known
+            .as_ref()
+            .and_then(|(cached, _)| cached.etag.as_deref());
+        download_repo_archive(location, &staging, etag, options, handle, app)
+    };
 
+    let result = match (fetched, known) {
+        (Ok(Some((tree, etag))), _) => cache
+            .insert(location, reference, tree, etag, depth)
+            .map(|tree| (tree, true)),
+        (Ok(None), Some((_, tree))) => Ok((tree, true)),
+        (Ok(None), None) => Err(anyhow::anyhow!(
+            "Server answered 304 Not Modified without a cached copy"
+        )),
+        (Err(e), Some((_, tree))) if !handle.is_cancelled() => {
+            eprintln!(
+                "Fetching {} failed, using the cached copy: {:#}",
+                location.url, e
+            );
+            Ok((tree, false))
+        }
+        (Err(e), _) => Err(e),
+    };
+
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Clones the whole tree of `location` into `dest_root`; the subdirectory is picked afterwards.
+fn clone_remote(
+    location: &RepoLocation,
+    dest_root: &Path,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+) -> Result<FetchedTree> {
+    let clone_url = location
+        .clone_url()
+        .with_context(|| format!("{} is an archive link and cannot be cloned", location.url))?;
+    let cloned = git::clone_repository(
+        &clone_url,
+        dest_root,
+        &location.name(),
+        location.reference.as_deref(),
+        None,
+        options.clone_depth,
+        handle.flag(),
+    )
+    .map_err(|e| {
+        if handle.is_cancelled() {
+            anyhow::anyhow!("Download cancelled")
+        } else {
+            e
+        }
+    })?;
+
+    Ok(FetchedTree {
+        files: cloned.path,
+        commit: Some(cloned.commit),
+        git_dir: Some(cloned.git_dir),
+    })
+}
+
+/// Downloads the provider archive of `location` into `dest_root` and extracts it, returning the
+/// extracted tree and the response ETag, or `None` when `etag` is still current (304). The
+/// commit comes from the zip comment (GitHub) or the `<name>-<sha>` top directory most hosts
+/// use. The archive is streamed to disk, reporting `download-progress` events.
+pub fn download_repo_archive(
+    location: &RepoLocation,
+    dest_root: &Path,
+    etag: Option<&str>,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: &AppHandle,
+) -> Result<Option<(FetchedTree, Option<String>)>> {
+    let token = credentials::token_for(&get_app_dir()?, location.host());
+    fetch_archive(
+        location,
+        dest_root,
+        etag,
+        options,
+        handle,
+        token.as_deref(),
+        |progress| {
+            let _ = app.emit("download-progress", progress);
+        },
+    )
+}
+
+/// `download_repo_archive` with the access token already looked up.
+fn fetch_archive(
+    location: &RepoLocation,
+    dest_root: &Path,
+    etag: Option<&str>,
+    options: &DownloadOptions,
+    handle: &DownloadHandle,
+    token: Option<&str>,
+    on_progress: impl FnMut(download::DownloadProgress),
+) -> Result<Option<(FetchedTree, Option<String>)>> {
+    let client = download::http_client(options)?;
+
+    let reference = match (location.reference.clone(), location.provider) {
+        (Some(reference), _) => reference,
+        // Gitea has no archive alias for the default branch, so look it up.
+        (None, Provider::Gitea) => gitea_default_branch(&client, location, token)?,
+        (None, _) => "HEAD".to_string(),
+    };
+
+    let archive_url = location
+        .archive_url(&reference, token.is_some())
+        .with_context(|| {
+            format!(
+                "{} has no archive download; fetch it with git instead",
+                location.url
+            )
+        })?;
+
+    let archive_path_part = archive_url.split(['?', '#']).next().unwrap_or_default();
+    let kind =
+        archive::archive_kind(Path::new(archive_path_part)).unwrap_or(archive::ArchiveKind::Zip);
+
+    let mut request = client.get(&archive_url);
+    if let Some(token) = token {
+        request = credentials::authorize(request, location.provider, token);
+    }
+    if let Some(etag) = etag {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    let response = request.send()?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(download_error(response.status(), location, token.is_some()));
+    }
+
+    let new_etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let archive_path = dest_root.join(match kind {
+        archive::ArchiveKind::Zip => "download.zip",
+        archive::ArchiveKind::Tar => "download.tar",
+        archive::ArchiveKind::TarGz => "download.tar.gz",
+        archive::ArchiveKind::TarXz => "download.tar.xz",
+    });
+    let total = response.content_length();
+    download::save_to_file(
+        response,
+        total,
+        &archive_path,
+        options.max_archive_size,
+        handle,
+        on_progress,
+    )?;
+
+    let mut commit = match kind {
+        archive::ArchiveKind::Zip => archive::zip_comment(&archive_path)
+            .ok()
+            .map(|comment| comment.trim().to_string())
+            .filter(|comment| git::looks_like_commit_sha(comment)),
+        _ => None,
+    };
+
+    let extract_root = dest_root.join(location.name());
+    archive::extract_archive(
+        &archive_path,
+        kind,
+        &extract_root,
+        &ExtractLimits::default(),
+    )?;
+    fs::remove_file(&archive_path)?;
+
+    let mut top_dir = extract_root.clone();
     let mut entries = fs::read_dir(&extract_root)?;
     if let Some(entry_result) = entries.next() {
         let entry = entry_result?;
         if entries.next().is_none() && entry.path().is_dir() {
-            return Ok(entry.path());
+            top_dir = entry.path();
+        }
+    }
+
+    if commit.is_none() && location.provider != Provider::Archive && top_dir != extract_root {
+        commit = top_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.rsplit_once('-'))
+            .map(|(_, suffix)| suffix.to_string())
+            .filter(|suffix| git::looks_like_commit_sha(suffix));
+    }
+
+    Ok(Some((
+        FetchedTree {
+            files: top_dir,
+            commit,
+            git_dir: None,
+        },
+        new_etag,
+    )))
+}
+
+/// Parses a GitHub pull request: its diff first, then the head versions of the changed files
+/// (and, with `include_neighbors`, the files next to them).
+pub fn parse_pull_request(
+    url: &str,
+    include_neighbors: bool,
+    options: ParseOptions,
+    app: AppHandle,
+) -> Result<ParseMetadata> {
+    let pr = pull_request::parse_pr_url(url)
+        .with_context(|| format!("Not a pull request URL: {}", url))?;
+
+    let repo_root = get_app_dir()?
+        .join(TEMP_REPOS_DIR)
+        .join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&repo_root)?;
+
+    let client = download::http_client(&DownloadOptions::default())?;
+    let host = pr.base_url.split_once("://").map_or("", |(_, host)| host);
+    let token = credentials::token_for(&get_app_dir()?, host);
+
+    let bundle = match pull_request::fetch_pull_request(
+        &client,
+        &pr,
+        token.as_deref(),
+        &repo_root,
+        include_neighbors,
+    ) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&repo_root);
+            return Err(e);
         }
+    };
+
+    let source = RemoteSource {
+        url: pr.url(),
+        reference: Some(bundle.head_ref.clone()),
+        commit: Some(bundle.head_sha.clone()),
+        ..Default::default()
+    };
+    source.save(&repo_root)?;
+
+    let sections = ExtraSections {
+        leading: vec![ContentSection {
+            title: format!("Pull request #{}: {} (diff)", pr.number, bundle.title),
+            body: bundle.diff,
+        }],
+        trailing: Vec::new(),
+    };
+
+    parse_files_with_sections(
+        vec![bundle.root.to_string_lossy().to_string()],
+        app,
+        Some(pr.url()),
+        options,
+        sections,
+        Vec::new(),
+    )
+}
+
+fn gitea_default_branch(
+    client: &reqwest::blocking::Client,
+    location: &RepoLocation,
+    token: Option<&str>,
+) -> Result<String> {
+    let api_url = format!("{}/api/v1/repos/{}", location.base_url, location.repo_path);
+    let mut request = client.get(&api_url);
+    if let Some(token) = token {
+        request = credentials::authorize(request, location.provider, token);
     }
 
-    Ok(extract_root)
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(download_error(response.status(), location, token.is_some()));
+    }
+
+    let repo: serde_json::Value = serde_json::from_reader(response)?;
+    repo.get("default_branch")
+        .and_then(|b| b.as_str())
+        .map(str::to_string)
+        .with_context(|| format!("No default branch reported for {}", location.url))
+}
+
+/// Explains failed downloads; hosts answer 404 rather than 401 for private repositories, so the
+/// missing-token hint is given for both.
+fn download_error(
+    status: reqwest::StatusCode,
+    location: &RepoLocation,
+    has_token: bool,
+) -> anyhow::Error {
+    let host = location.host();
+    match (status.as_u16(), has_token) {
+        (401, true) => anyhow::anyhow!(
+            "The access token for {} was rejected (HTTP 401). It may be expired or revoked.",
+            host
+        ),
+        (401, false) => anyhow::anyhow!(
+            "{} requires authentication (HTTP 401). Add an access token for {}.",
+            location.url,
+            host
+        ),
+        (403, true) => anyhow::anyhow!(
+            "Access to {} was denied (HTTP 403). The token for {} may lack read access to the repository, or the API rate limit was hit.",
+            location.url,
+            host
+        ),
+        (403, false) => anyhow::anyhow!(
+            "Access to {} was denied (HTTP 403). The repository may be private or the API rate limit was hit; adding an access token for {} helps with both.",
+            location.url,
+            host
+        ),
+        (404, true) => anyhow::anyhow!(
+            "{} was not found (HTTP 404). Check the URL and ref, and that the token for {} can read this repository.",
+            location.url,
+            host
+        ),
+        (404, false) => anyhow::anyhow!(
+            "{} was not found (HTTP 404). Check the URL and ref; if the repository is private, add an access token for {}.",
+            location.url,
+            host
+        ),
+        _ => anyhow::anyhow!("Failed to download repository: HTTP {}", status),
+    }
 }
 
 fn sanitize_repo_url(input: &str) -> String {
@@ -193,6 +868,240 @@ fn sanitize_repo_url(input: &str) -> String {
     }
 }
 
+fn find_remote_source(paths: &[String]) -> Result<Option<RemoteSource>> {
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    Ok(paths
+        .iter()
+        .find_map(|path| RemoteSource::find(Path::new(path), &temp_repos_root)))
+}
+
+/// Introduces one source of a combined parse: where it came from and, for repositories, what
+/// their host reported.
+fn source_section(
+    summary: &SourceSummary,
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<ContentSection> {
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    let body = match RemoteSource::find(path, &temp_repos_root).filter(|_| summary.url.is_some()) {
+        Some(source) => {
+            let info = source
+                .repository
+                .filter(|_| options.repository_header)
+                .unwrap_or_default();
+            repo_info::header_text(
+                &source.url,
+                source.reference.as_deref(),
+                source.commit.as_deref(),
+                &info,
+            )
+        }
+        None => format!("Path: {}\n", summary.path.as_deref().unwrap_or_default()),
+    };
+
+    Ok(ContentSection {
+        title: format!("Source: {}", summary.name),
+        body,
+    })
+}
+
+/// The distinct local working trees the parsed paths are in; fetched repositories and extracted
+/// archives in `temp-repos` are not looked at.
+fn local_worktrees(paths: &[String]) -> Result<Vec<gix::Repository>> {
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    let mut seen = HashSet::new();
+    Ok(paths
+        .iter()
+        .map(Path::new)
+        .filter(|path| !path.starts_with(&temp_repos_root))
+        .filter_map(git::discover_worktree)
+        .filter(|repo| seen.insert(repo.workdir().map(Path::to_path_buf)))
+        .collect())
+}
+
+/// Git state of the working tree the parsed paths are in. Left out when they span several,
+/// which combined parses record per source instead.
+fn local_worktree_state(paths: &[String]) -> Result<Option<WorktreeState>> {
+    match local_worktrees(paths)?.as_slice() {
+        [repo] => Ok(describe_worktree(repo)),
+        _ => Ok(None),
+    }
+}
+
+fn describe_worktree(repo: &gix::Repository) -> Option<WorktreeState> {
+    match git::worktree_state(repo) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            eprintln!("Failed to read the git state of the parsed files: {:#}", e);
+            None
+        }
+    }
+}
+
+/// One `git diff HEAD` section per local working tree among `paths` that has uncommitted
+/// changes, named after the tree when there are several. Like the files themselves, the diff
+/// only covers what is below a parsed path and passes `filter`.
+fn uncommitted_sections(
+    paths: &[String],
+    options: &ParseOptions,
+    filter: &PathFilter,
+) -> Result<Vec<ContentSection>> {
+    let repos = local_worktrees(paths)?;
+    let roots: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+
+    let mut sections = Vec::new();
+    for repo in &repos {
+        let diff = git::uncommitted_diff(repo, options.max_file_size, |path| {
+            roots
+                .iter()
+                .any(|root| path.starts_with(root) && filter.accepts_below(root, path))
+        })?;
+        if diff.is_empty() {
+            continue;
+        }
+        let title = match repo.workdir().and_then(Path::file_name) {
+            Some(name) if repos.len() > 1 => {
+                format!("Uncommitted changes: {}", name.to_string_lossy())
+            }
+            _ => "Uncommitted changes".to_string(),
+        };
+        sections.push(ContentSection { title, body: diff });
+    }
+    Ok(sections)
+}
+
+/// Parsed files of one repository with their paths relative to its root.
+struct RepoFiles<'a> {
+    name: String,
+    repo: gix::Repository,
+    tip: String,
+    files: Vec<(&'a FileMetadata, PathBuf)>,
+}
+
+/// One history section per git repository among `paths`: local working trees and repositories
+/// fetched with git. Archive downloads carry no history and are passed over.
+fn history_sections(
+    paths: &[String],
+    parsed_files: &[FileMetadata],
+    options: &ParseOptions,
+) -> Result<Vec<ContentSection>> {
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    let mut repos: Vec<RepoFiles> = Vec::new();
+
+    for path_str in paths {
+        let path = Path::new(path_str);
+        let below = parsed_files
+            .iter()
+            .filter(|file| Path::new(&file.path).starts_with(path));
+
+        let (name, repo, tip, files) = match RemoteSource::find(path, &temp_repos_root) {
+            Some(source) => {
+                let (Some(git_dir), Some(commit)) = (source.git_dir, source.commit) else {
+                    continue;
+                };
+                let Some(files_root) = remote::files_root_of(path, &temp_repos_root) else {
+                    continue;
+                };
+                let files: Vec<_> = below
+                    .filter_map(|file| {
+                        let relative = Path::new(&file.path).strip_prefix(&files_root).ok()?;
+                        Some((file, relative.to_path_buf()))
+                    })
+                    .collect();
+                let repo = git::open_repository(Path::new(&git_dir))?;
+                (file_name_of(&files_root), repo, commit, files)
+            }
+            None => {
+                let Some(repo) = git::discover_worktree(path) else {
+                    continue;
+                };
+                let files: Vec<_> = below
+                    .filter_map(|file| {
+                        Some((file, git::worktree_relative(&repo, Path::new(&file.path))?))
+                    })
+                    .collect();
+                let name = repo.workdir().map(file_name_of).unwrap_or_default();
+                (name, repo, "HEAD".to_string(), files)
+            }
+        };
+
+        match repos
+            .iter_mut()
+            .find(|known| known.repo.git_dir() == repo.git_dir())
+        {
+            Some(known) => known.files.extend(files),
+            None => repos.push(RepoFiles {
+                name,
+                repo,
+                tip,
+                files,
+            }),
+        }
+    }
+
+    let mut sections = Vec::new();
+    for entry in &repos {
+        let relative: Vec<PathBuf> = entry.files.iter().map(|(_, path)| path.clone()).collect();
+        let history =
+            match git::history(&entry.repo, &entry.tip, options.history_commits, &relative) {
+                Ok(history) => history,
+                Err(e) => {
+                    eprintln!("Failed to read the git history of {}: {:#}", entry.name, e);
+                    continue;
+                }
+            };
+
+        let mut body = String::from("Recent commits:\n");
+        for commit in &history.commits {
+            body.push_str(&commit_line(commit));
+        }
+        body.push_str("\nLast changed in:\n");
+        for (file, relative) in &entry.files {
+            if let Some(commit) = history.last_changes.get(relative) {
+                body.push_str(&format!("{}: {}", file.relative_path, commit_line(commit)));
+            }
+        }
+
+        let title = match repos.len() {
+            1 => "History".to_string(),
+            _ => format!("History: {}", entry.name),
+        };
+        sections.push(ContentSection { title, body });
+    }
+    Ok(sections)
+}
+
+/// `<short id> <date> <author>: <summary>`, like a compact `git log` line.
+fn commit_line(commit: &git::CommitSummary) -> String {
+    let date = DateTime::from_timestamp(commit.time, 0)
+        .map(|time| time.with_timezone(&Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+    format!(
+        "{} {} {}: {}\n",
+        &commit.id[..commit.id.len().min(7)],
+        date,
+        commit.author,
+        commit.summary
+    )
+}
+
+/// The header section of a remote parse whose host described the repository.
+fn repository_header(source: &RemoteSource) -> Option<ContentSection> {
+    let info = source.repository.as_ref()?;
+    Some(ContentSection {
+        title: "Repository".to_string(),
+        body: repo_info::header_text(
+            &source.url,
+            source.reference.as_deref(),
+            source.commit.as_deref(),
+            info,
+        ),
+    })
+}
+
 fn cleanup_temp_repos(paths: &[String]) -> Result<()> {
     let app_dir = get_app_dir()?;
     let temp_repos_root = app_dir.join(TEMP_REPOS_DIR);
@@ -206,12 +1115,9 @@ fn cleanup_temp_repos(paths: &[String]) -> Result<()> {
     for path_str in paths {
         let path = Path::new(path_str);
 
-        if let Ok(stripped) = path.strip_prefix(&temp_repos_root) {
-            if let Some(first_component) = stripped.components().next() {
-                let repo_root = temp_repos_root.join(first_component.as_os_str());
-                if repo_root.exists() {
-                    roots_to_delete.insert(repo_root);
-                }
+        if let Some(repo_root) = remote::repo_root_of(path, &temp_repos_root) {
+            if repo_root.exists() {
+                roots_to_delete.insert(repo_root);
             }
         }
     }
@@ -225,84 +1131,668 @@ fn cleanup_temp_repos(paths: &[String]) -> Result<()> {
     Ok(())
 }
 
+/// Deletes `temp-repos` entries and unfinished cache downloads last modified more than
+/// `max_age` ago. Returns how many were removed.
+pub fn remove_stale_temp_repos(max_age: Duration) -> Result<usize> {
+    let app_dir = get_app_dir()?;
+    let roots = [
+        app_dir.join(TEMP_REPOS_DIR),
+        app_dir.join(REPO_CACHE_DIR).join(cache::STAGING_DIR),
+    ];
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for root in roots.iter().filter(|root| root.is_dir()) {
+        for entry in fs::read_dir(root)? {
+            let entry = entry?;
+            let age = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok());
+
+            if age.is_some_and(|age| age > max_age) {
+                let path = entry.path();
+                let result = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+                match result {
+                    Ok(()) => removed += 1,
+                    Err(e) => eprintln!("Failed to remove temp repo {:?}: {}", path, e),
+                }
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes the `temp-repos` roots of the parsed paths when dropped, so failed parses clean up
+/// as well.
+struct TempRepoCleanup<'a>(&'a [String]);
+
+impl Drop for TempRepoCleanup<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = cleanup_temp_repos(self.0) {
+            eprintln!("Failed to clean up temp repos: {}", e);
+        }
+    }
+}
+
+/// Removes a parse directory when dropped unless disarmed, so a parse that fails part way does
+/// not leave a half-written entry in the parse list.
+struct ParseDirCleanup<'a> {
+    dir: &'a Path,
+    armed: bool,
+}
+
+impl<'a> ParseDirCleanup<'a> {
+    fn new(dir: &'a Path) -> Self {
+        Self { dir, armed: true }
+    }
+
+    fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ParseDirCleanup<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Err(e) = fs::remove_dir_all(self.dir) {
+                eprintln!("Failed to remove {}: {}", self.dir.display(), e);
+            }
+        }
+    }
+}
+
 // /////////////////////////////////////////////////////////////////////////////
 // Main Parsing Logic
 // /////////////////////////////////////////////////////////////////////////////
-fn is_valid_path(path: &Path) -> bool {
+pub fn is_valid_path(path: &Path, root: &Path) -> bool {
+    path.exists() && ignore_rule(path, root).is_none()
+}
+
+/// Names the rule that excludes `path` from a parse of `root`, if any, using the saved
+/// exclusion list.
+fn ignore_rule(path: &Path, root: &Path) -> Option<&'static str> {
+    match get_app_dir() {
+        Ok(app_dir) => ignore_rule_with(path, root, &exclusions::saved(&app_dir)),
+        Err(_) => ignore_rule_with(path, root, &Exclusions::default()),
+    }
+}
+
+fn ignore_rule_with(path: &Path, root: &Path, exclusions: &Exclusions) -> Option<&'static str> {
     let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    path.exists() && !path.is_symlink() && !file_name.starts_with('.')
+    if path.is_symlink() {
+        Some("symlink")
+    } else if file_name.starts_with('.') {
+        Some("hidden file")
+    } else if exclusions.is_excluded(path, root) {
+        Some("matches an exclusion pattern")
+    } else {
+        None
+    }
+}
+
+/// The rules of `ignore_rule`, with the parse's own exclusion list if it has one, plus those
+/// that depend on the parse, e.g. leaving out files git does not track, that did not change
+/// since a base ref or that are generated or vendored.
+#[derive(Default)]
+struct PathFilter {
+    /// The parsed paths exclusions are matched against, longest first.
+    roots: Vec<PathBuf>,
+    tracked: Vec<WorktreeFiles>,
+    changed: Vec<Changes>,
+    base_ref: Option<String>,
+    /// Directories whose `.gitattributes` apply to everything below them, longest first.
+    attribute_roots: Vec<PathBuf>,
+    linguist: Option<LinguistAttributes>,
+    exclusions: Arc<Exclusions>,
+}
+
+impl PathFilter {
+    fn new(paths: &[String], options: &ParseOptions) -> Result<Self> {
+        let exclusions = match &options.exclusions {
+            Some(patterns) => Arc::new(Exclusions::new(patterns)?),
+            None => exclusions::saved(&get_app_dir()?),
+        };
+        let mut roots: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        roots.sort_by_key(|root| std::cmp::Reverse(root.components().count()));
+        let mut filter = Self {
+            roots,
+            base_ref: options.changed_since.clone(),
+            exclusions,
+            ..Self::default()
+        };
+        if options.linguist_attributes {
+            let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+            filter.attribute_roots = paths
+                .iter()
+                .map(|path| attributes_root(Path::new(path), &temp_repos_root))
+                .collect();
+            filter
+                .attribute_roots
+                .sort_by_key(|root| std::cmp::Reverse(root.components().count()));
+            filter.linguist = Some(LinguistAttributes::default());
+        }
+        let repos = paths
+            .iter()
+            .filter_map(|path| git::discover_worktree(Path::new(path)));
+
+        for repo in repos {
+            if options.tracked_only {
+                filter.tracked.push(git::tracked_files(&repo)?);
+            }
+            if let Some(base_ref) = &options.changed_since {
+                let diff_limit = options.include_diffs.then_some(options.max_file_size);
+                filter
+                    .changed
+                    .push(git::changes_since(&repo, base_ref, diff_limit)?);
+            }
+        }
+
+        if let Some(base_ref) = &filter.base_ref {
+            if filter.changed.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Changes since '{}' can only be parsed inside a git working tree",
+                    base_ref
+                ));
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The parsed path `path` lies under; a path outside all of them is its own root.
+    fn root_of<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.roots
+            .iter()
+            .find(|root| path.starts_with(root))
+            .map_or(path, PathBuf::as_path)
+    }
+
+    fn ignore_rule(&self, path: &Path) -> Option<&'static str> {
+        self.ignore_rule_below(path, self.root_of(path))
+    }
+
+    fn ignore_rule_below(&self, path: &Path, root: &Path) -> Option<&'static str> {
+        ignore_rule_with(path, root, &self.exclusions)
+            .or_else(|| {
+                let tracked = self.tracked.iter().find_map(|files| files.contains(path))?;
+                (!tracked).then_some("not tracked by git")
+            })
+            .or_else(|| {
+                self.base_ref.as_ref()?;
+                let changed = self
+                    .changed
+                    .iter()
+                    .find_map(|changes| changes.paths.contains(path));
+                match changed {
+                    Some(true) => None,
+                    Some(false) => Some("unchanged since the base ref"),
+                    None => Some("not in a git working tree"),
+                }
+            })
+            .or_else(|| {
+                let linguist = self.linguist.as_ref()?;
+                let root = self
+                    .attribute_roots
+                    .iter()
+                    .find(|root| path.starts_with(root))?;
+                if !path.is_file() {
+                    return None;
+                }
+                linguist.mark(path, root).map(|mark| mark.description())
+            })
+    }
+
+    fn is_valid(&self, path: &Path) -> bool {
+        path.exists() && self.ignore_rule(path).is_none()
+    }
+
+    /// Whether the walk from `root` down to `path` would reach it. `path` may be deleted, in
+    /// which case only the rules that do not need the file are applied to it.
+    fn accepts_below(&self, root: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+        let passes = |path: &Path| {
+            let rule = if path.exists() {
+                self.ignore_rule_below(path, root)
+            } else {
+                ignore_rule_with(path, root, &self.exclusions)
+            };
+            rule.is_none()
+        };
+
+        let mut current = root.to_path_buf();
+        if !passes(&current) {
+            return false;
+        }
+        for component in relative.components() {
+            current.push(component);
+            if !passes(&current) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Like `is_valid`, but records why an existing path was left out.
+    fn check(&self, path: &Path, base_path: BasePath, skipped: &mut SkipReport) -> bool {
+        match self.ignore_rule(path) {
+            Some(rule) => {
+                skipped.record(path, &base_path.relative(path), SkipReason::Ignored, rule);
+                false
+            }
+            None => path.exists(),
+        }
+    }
+
+    /// The section with the diff of a parsed file against the base ref, if one was computed.
+    fn diff_section(&self, path: &Path, base_path: BasePath) -> Option<ContentSection> {
+        let base_ref = self.base_ref.as_ref()?;
+        let diff = self
+            .changed
+            .iter()
+            .find_map(|changes| changes.get(path))?
+            .diff
+            .clone()?;
+        let relative = base_path.relative(path);
+        Some(ContentSection {
+            title: format!("{} (diff since {})", relative.display(), base_ref),
+            body: diff,
+        })
+    }
+
+    fn changes(&self) -> Option<ChangeSummary> {
+        Some(ChangeSummary {
+            base_ref: self.base_ref.clone()?,
+            base_commit: self.changed.first()?.base_commit.clone(),
+            files: self
+                .changed
+                .iter()
+                .flat_map(|changes| changes.files.iter().cloned())
+                .collect(),
+        })
+    }
+}
+
+/// Where the `.gitattributes` that apply to `path` start: its git working tree, the files of
+/// the fetched repository it is in, or else the parsed directory itself.
+fn attributes_root(path: &Path, temp_repos_root: &Path) -> PathBuf {
+    let worktree =
+        git::discover_worktree(path).and_then(|repo| repo.workdir().map(Path::to_path_buf));
+    if let Some(worktree) = worktree {
+        return worktree;
+    }
+    if let Some(files_root) = remote::files_root_of(path, temp_repos_root) {
+        return files_root;
+    }
+    if path.is_file() {
+        path.parent().unwrap_or(path).to_path_buf()
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// What the files of a parsed path are shown relative to. Directories are parsed relative to
+/// themselves, files relative to their directory; a named root puts its name in front.
+#[derive(Clone, Copy)]
+struct BasePath<'a> {
+    dir: &'a Path,
+    name: Option<&'a str>,
+}
+
+impl<'a> BasePath<'a> {
+    fn of(path: &'a Path, name: Option<&'a str>) -> Self {
+        if path.is_file() {
+            let name = name.filter(|name| *name != file_name_of(path));
+            Self {
+                dir: path.parent().unwrap_or(path),
+                name,
+            }
+        } else {
+            Self { dir: path, name }
+        }
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        let relative = path.strip_prefix(self.dir).unwrap_or(path);
+        match self.name {
+            Some(name) if relative.as_os_str().is_empty() => PathBuf::from(name),
+            Some(name) => Path::new(name).join(relative),
+            None => relative.to_path_buf(),
+        }
+    }
+}
+
+/// Gives every source a unique name, suffixing names that clash. The files stay where they are;
+/// the name is only put in front of their relative paths.
+fn name_sources_uniquely(summaries: &mut [SourceSummary]) {
+    let mut taken = HashSet::new();
+
+    for summary in summaries.iter_mut() {
+        let name = (1..)
+            .map(|n| match n {
+                1 => summary.name.clone(),
+                n => format!("{}-{}", summary.name, n),
+            })
+            .find(|candidate| !taken.contains(candidate))
+            .unwrap_or_default();
+        taken.insert(name.clone());
+        summary.name = name;
+    }
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "source".to_string())
 }
 
+/// Replaces archive files among `paths` with directories they are extracted to under
+/// `temp-repos`, so they are parsed like folders and removed with the other temp repos. Returns
+/// one entry per path, `None` for archives that could not be extracted.
+fn expand_archives(paths: Vec<String>, skipped: &mut SkipReport) -> Result<Vec<Option<String>>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+
+    for path_str in paths {
+        let path = Path::new(&path_str);
+        let kind = match archive::archive_kind(path) {
+            Some(kind) if path.is_file() => kind,
+            _ => {
+                expanded.push(Some(path_str));
+                continue;
+            }
+        };
+
+        let file_name = path.file_name().unwrap_or_default();
+        let extract_dir = get_app_dir()?
+            .join(TEMP_REPOS_DIR)
+            .join(Uuid::new_v4().to_string())
+            .join(file_name);
+
+        match archive::extract_archive(path, kind, &extract_dir, &ExtractLimits::default()) {
+            Ok(()) => expanded.push(Some(extract_dir.to_string_lossy().to_string())),
+            Err(e) => {
+                // Nothing refers to the half-extracted temp repo yet, so it is removed here.
+                if let Some(temp_root) = extract_dir.parent() {
+                    let _ = fs::remove_dir_all(temp_root);
+                }
+                skipped.record(
+                    path,
+                    Path::new(file_name),
+                    SkipReason::ReadError,
+                    format!("{:#}", e),
+                );
+                expanded.push(None);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
 
 pub fn parse_files(
     paths: Vec<String>,
     app: AppHandle,
     remote_url: Option<String>,
+    options: ParseOptions,
 ) -> Result<ParseMetadata> {
-    let remote_url_str = remote_url.unwrap_or_default();
-    let (parse_dir, mut output_file, parse_id) = create_parse_directory(&remote_url_str)?;
+    parse_files_with_sections(
+        paths,
+        app,
+        remote_url,
+        options,
+        ExtraSections::default(),
+        Vec::new(),
+    )
+}
 
-    let total_files = count_text_files(&paths)?;
+/// Fetches the remote sources and parses them together with the local ones. Each source is
+/// rooted under its own name (repository or directory name, suffixed when two clash) in
+/// `tree.json` and `content.txt`, and introduced by a `Source: <name>` section.
+pub fn parse_sources(
+    sources: Vec<SourceInput>,
+    options: ParseOptions,
+    download_options: &DownloadOptions,
+    handle: &DownloadHandle,
+    app: AppHandle,
+) -> Result<ParseMetadata> {
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No sources to parse"));
+    }
+
+    // Sparse fetches can leave out what this parse would skip as too large anyway.
+    let mut download_options = download_options.clone();
+    download_options.max_file_size = match options.oversize_strategy {
+        OversizeStrategy::Skip => Some(options.max_file_size),
+        _ => None,
+    };
+    // A history section cannot list commits a shallow clone did not fetch.
+    if options.history_commits > 0 && download_options.clone_depth != 0 {
+        let wanted = u32::try_from(options.history_commits).unwrap_or(u32::MAX);
+        download_options.clone_depth = download_options.clone_depth.max(wanted);
+    }
+
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    let mut paths = Vec::with_capacity(sources.len());
+    let mut summaries = Vec::with_capacity(sources.len());
+
+    let result = (|| -> Result<()> {
+        for source in sources {
+            match source {
+                SourceInput::Local { path } => {
+                    summaries.push(SourceSummary {
+                        name: file_name_of(Path::new(&path)),
+                        path: Some(path.clone()),
+                        url: None,
+                        git_ref: None,
+                        commit_sha: None,
+                        worktree: git::discover_worktree(Path::new(&path))
+                            .and_then(|repo| describe_worktree(&repo)),
+                    });
+                    paths.push(path);
+                }
+                SourceInput::Remote {
+                    url,
+                    reference,
+                    method,
+                    provider,
+                } => {
+                    let location = remote::parse_repo_url(&url, reference.as_deref(), provider)?;
+                    let fetched = fetch_repository(
+                        &url,
+                        reference.as_deref(),
+                        method,
+                        provider,
+                        &download_options,
+                        handle,
+                        &app,
+                    )?;
+                    let remote = RemoteSource::find(&fetched, &temp_repos_root);
+                    summaries.push(SourceSummary {
+                        name: location.name(),
+                        path: None,
+                        url: Some(url),
+                        git_ref: location.reference.clone(),
+                        commit_sha: remote.and_then(|r| r.commit),
+                        worktree: None,
+                    });
+                    paths.push(fetched.to_string_lossy().to_string());
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = cleanup_temp_repos(&paths);
+        return Err(e);
+    }
+
+    name_sources_uniquely(&mut summaries);
+    let remote_url = summaries.iter().find_map(|summary| summary.url.clone());
+    parse_files_with_sections(
+        paths,
+        app,
+        remote_url,
+        options,
+        ExtraSections::default(),
+        summaries,
+    )
+}
+
+/// With `sources` (one per path) every path is rooted under its own name, see `parse_sources`;
+/// otherwise directories are parsed relative to themselves.
+pub fn parse_files_with_sections(
+    paths: Vec<String>,
+    app: AppHandle,
+    remote_url: Option<String>,
+    options: ParseOptions,
+    sections: ExtraSections,
+    sources: Vec<SourceSummary>,
+) -> Result<ParseMetadata> {
+    let named_roots = !sources.is_empty();
+    let remote_url_str = remote_url.unwrap_or_default();
+    let (parse_dir, output_file, parse_id) = create_parse_directory(&remote_url_str)?;
+    let mut parse_dir_cleanup = ParseDirCleanup::new(&parse_dir);
+    // Bound after the guard so the file is closed before the directory is removed.
+    let mut output_file = output_file;
+
+    let temp_repos_root = get_app_dir()?.join(TEMP_REPOS_DIR);
+    let mut skipped = SkipReport::default();
+    // Which of `sources` each remaining path belongs to, as failed archives drop out.
+    let (paths, source_indices): (Vec<String>, Vec<usize>) = expand_archives(paths, &mut skipped)?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, path)| path.map(|path| (path, index)))
+        .unzip();
+    let _cleanup = TempRepoCleanup(&paths);
+
+    let filter = PathFilter::new(&paths, &options)?;
+    let total_files = count_text_files(&paths, &filter)?;
 
     emit_progress(&app, &parse_id, 0, total_files, None);
 
+    // With several sources no single repository describes the parse; see `sources` instead.
+    let source = if sources.len() > 1 {
+        None
+    } else {
+        find_remote_source(&paths)?
+    };
+    if options.repository_header && !named_roots {
+        if let Some(header) = source.as_ref().and_then(repository_header) {
+            write_section(&mut output_file, &header)?;
+        }
+    }
+
+    for section in &sections.leading {
+        write_section(&mut output_file, section)?;
+    }
+
     let mut parsed_files = Vec::new();
     let mut total_size = 0u64;
     let mut current_count = 0;
     let mut file_tree = Vec::new();
 
-    for path_str in &paths {
+    let source_names: Vec<Option<&str>> = (0..paths.len())
+        .map(|index| {
+            let summary = source_indices.get(index).and_then(|&i| sources.get(i))?;
+            Some(summary.name.as_str())
+        })
+        .collect();
+
+    for (path_str, name) in paths.iter().zip(&source_names) {
         let path = Path::new(path_str);
-        if is_valid_path(path) {
-            let base_path = if path.is_file() {
-                path.parent().unwrap_or(path)
-            } else {
-                path
-            };
+        if filter.is_valid(path) {
+            let base_path = BasePath::of(path, *name);
 
-            if let Ok(tree) = build_file_tree(path, base_path) {
+            if let Ok(tree) = build_file_tree(path, base_path, &filter) {
                 file_tree.push(tree);
             }
         }
     }
 
-    if total_files > 0 {
-        for path_str in &paths {
-            let path = Path::new(&path_str);
-            if !is_valid_path(&path){ continue; }
+    for (index, path_str) in paths.iter().enumerate() {
+        let path = Path::new(&path_str);
+        let base_path = BasePath::of(path, source_names[index]);
 
-            let base_path = if path.is_file() {
-                path.parent().unwrap_or(path)
-            } else {
-                path
-            };
+        if let Some(summary) = source_indices.get(index).and_then(|&i| sources.get(i)) {
+            write_section(&mut output_file, &source_section(summary, path, &options)?)?;
+        }
 
-            if path.is_dir() {
-                process_directory_with_progress(
+        // Files a sparse fetch never downloaded are reported as if the parse had skipped them.
+        if let Some(remote) = RemoteSource::find(path, &temp_repos_root) {
+            for relative in &remote.too_large {
+                let path = path.join(relative);
+                skipped.record(
                     &path,
-                    base_path,
-                    &mut output_file,
-                    &mut parsed_files,
-                    &mut total_size,
-                    &mut current_count,
-                    total_files,
-                    &app,
-                    &parse_id,
-                )?;
-            } else if process_single_text_file(
-                &path,
+                    &base_path.relative(&path),
+                    SkipReason::TooLarge,
+                    format!(
+                        "larger than the {} limit",
+                        format_size(options.max_file_size)
+                    ),
+                );
+            }
+        }
+
+        if !filter.check(path, base_path, &mut skipped) {
+            continue;
+        }
+
+        if path.is_dir() {
+            process_directory_with_progress(
+                path,
                 base_path,
                 &mut output_file,
                 &mut parsed_files,
                 &mut total_size,
-            )? {
-                current_count += 1;
-                emit_progress(&app, &parse_id, current_count, total_files, None);
+                &mut current_count,
+                total_files,
+                &app,
+                &parse_id,
+                &options,
+                &filter,
+                &mut skipped,
+            )?;
+        } else if process_single_text_file(
+            path,
+            base_path,
+            &mut output_file,
+            &mut parsed_files,
+            &mut total_size,
+            &options,
+            &mut skipped,
+        )? {
+            if let Some(section) = filter.diff_section(path, base_path) {
+                write_section(&mut output_file, &section)?;
             }
+            current_count += 1;
+            emit_progress(&app, &parse_id, current_count, total_files, None);
         }
     }
 
-    cleanup_temp_repos(&paths)?;
+    for section in &sections.trailing {
+        write_section(&mut output_file, section)?;
+    }
+
+    if options.history_commits > 0 {
+        for section in history_sections(&paths, &parsed_files, &options)? {
+            write_section(&mut output_file, &section)?;
+        }
+    }
+
+    if options.uncommitted_diff {
+        for section in uncommitted_sections(&paths, &options, &filter)? {
+            write_section(&mut output_file, &section)?;
+        }
+    }
 
     let now = Local::now();
 
@@ -315,8 +1805,27 @@ pub fn parse_files(
         updated_at: now,
         files_count: parsed_files.len(),
         total_size,
+        stats: stats::compute_stats(&parsed_files),
+        skipped: skipped.summary(),
+        transcoded_files: parsed_files
+            .iter()
+            .filter(|file| file.encoding != "UTF-8" || file.had_bom)
+            .map(|file| TranscodedFile {
+                relative_path: file.relative_path.clone(),
+                encoding: file.encoding.clone(),
+                had_bom: file.had_bom,
+            })
+            .collect(),
+        git_ref: source.as_ref().and_then(|s| s.reference.clone()),
+        commit_sha: source.as_ref().and_then(|s| s.commit.clone()),
+        repository: source.and_then(|s| s.repository),
+        sources,
+        worktree: local_worktree_state(&paths)?,
+        changes: filter.changes(),
     };
 
+    skipped.save(&parse_dir)?;
+
     let metadata_path = parse_dir.join(METADATA_FILENAME);
     let meta_file = File::create(&metadata_path)?;
     serde_json::to_writer_pretty(meta_file, &metadata)?;
@@ -324,6 +1833,7 @@ pub fn parse_files(
     let tree_path = parse_dir.join(TREE_FILENAME);
     let tree_file = File::create(&tree_path)?;
     serde_json::to_writer_pretty(tree_file, &file_tree)?;
+    parse_dir_cleanup.disarm();
 
     let content_path = get_content_path(&parse_dir);
 
@@ -340,7 +1850,7 @@ pub fn parse_files(
 
 fn process_directory_with_progress(
     dir: &Path,
-    base_path: &Path,
+    base_path: BasePath,
     output_file: &mut File,
     parsed_files: &mut Vec<FileMetadata>,
     total_size: &mut u64,
@@ -348,12 +1858,15 @@ fn process_directory_with_progress(
     total_files: usize,
     app: &AppHandle,
     parse_id: &str,
+    options: &ParseOptions,
+    filter: &PathFilter,
+    skipped: &mut SkipReport,
 ) -> Result<()> {
     if let Ok(entries) = fs::read_dir(dir) {
         for entry in entries.flatten() {
             let path = entry.path();
 
-            if !is_valid_path(&path){
+            if !filter.check(&path, base_path, skipped) {
                 continue;
             }
 
@@ -368,12 +1881,24 @@ fn process_directory_with_progress(
                     total_files,
                     app,
                     parse_id,
+                    options,
+                    filter,
+                    skipped,
                 );
             } else {
-                if let Ok(processed) =
-                    process_single_text_file(&path, base_path, output_file, parsed_files, total_size)
-                {
+                if let Ok(processed) = process_single_text_file(
+                    &path,
+                    base_path,
+                    output_file,
+                    parsed_files,
+                    total_size,
+                    options,
+                    skipped,
+                ) {
                     if processed {
+                        if let Some(section) = filter.diff_section(&path, base_path) {
+                            write_section(output_file, &section)?;
+                        }
                         *current_count += 1;
                         emit_progress(app, parse_id, *current_count, total_files, None);
                     }
@@ -386,18 +1911,48 @@ fn process_directory_with_progress(
 
 fn process_single_text_file(
     path: &Path,
-    base_path: &Path,
+    base_path: BasePath,
     output_file: &mut File,
     parsed_files: &mut Vec<FileMetadata>,
     total_size: &mut u64,
+    options: &ParseOptions,
+    skipped: &mut SkipReport,
 ) -> Result<bool> {
+    let relative_path = base_path.relative(path);
     if !is_text_file(path) {
+        match File::open(path) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => skipped.record(
+                path,
+                &relative_path,
+                SkipReason::PermissionDenied,
+                e.to_string(),
+            ),
+            _ => skipped.record(path, &relative_path, SkipReason::Binary, "binary content"),
+        }
         return Ok(false);
     }
 
-    match write_file_content(path, base_path, output_file) {
-        Ok(_) => {
-            if let Ok(metadata) = build_file_metadata(path, base_path) {
+    match write_file_content(path, &relative_path, output_file, options) {
+        Ok(None) => {
+            skipped.record(
+                path,
+                &relative_path,
+                SkipReason::TooLarge,
+                format!(
+                    "larger than the {} limit",
+                    format_size(options.max_file_size)
+                ),
+            );
+            Ok(true)
+        }
+        Ok(Some(decoded)) => {
+            if let Ok(mut metadata) = build_file_metadata(path, &relative_path) {
+                let content = &decoded.content;
+                metadata.language = stats::detect_language(path, content).to_string();
+                metadata.lines = stats::count_lines(content);
+                metadata.tokens = stats::estimate_tokens(content);
+                metadata.encoding = decoded.encoding.name().to_string();
+                metadata.had_bom = decoded.had_bom;
                 *total_size += metadata.size;
                 parsed_files.push(metadata);
                 Ok(true)
@@ -407,6 +1962,12 @@ fn process_single_text_file(
         }
         Err(e) => {
             eprintln!("Skipping file due to read/write error: {:?} - {}", path, e);
+            skipped.record(
+                path,
+                &relative_path,
+                skip_report::reason_for_error(&e),
+                format!("{:#}", e),
+            );
             Ok(true)
         }
     }
@@ -415,18 +1976,18 @@ fn process_single_text_file(
 // /////////////////////////////////////////////////////////////////////////////
 // File System & Tree Building
 // /////////////////////////////////////////////////////////////////////////////
-fn get_recursive_dir_size(path: &Path) -> u64 {
+fn get_recursive_dir_size(path: &Path, root: &Path) -> u64 {
     let mut total_size = 0;
     if let Ok(entries) = fs::read_dir(path) {
         for entry in entries.flatten() {
             let child_path = entry.path();
 
-            if !is_valid_path(&child_path) {
+            if !is_valid_path(&child_path, root) {
                 continue;
             }
 
             if child_path.is_dir() {
-                total_size += get_recursive_dir_size(&child_path);
+                total_size += get_recursive_dir_size(&child_path, root);
             } else if let Ok(metadata) = fs::metadata(&child_path) {
                 total_size += metadata.len();
             }
@@ -435,15 +1996,17 @@ fn get_recursive_dir_size(path: &Path) -> u64 {
     total_size
 }
 
-fn build_file_tree(path: &Path, base_path: &Path) -> Result<ParsedPath> {
-    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-    let file_path = path.to_string_lossy().to_string();
-
-    let relative_path = path
-        .strip_prefix(base_path)
-        .unwrap_or(path)
+fn build_file_tree(path: &Path, base_path: BasePath, filter: &PathFilter) -> Result<ParsedPath> {
+    let relative = base_path.relative(path);
+    // A named root is shown under its name.
+    let name = relative
+        .file_name()
+        .or(path.file_name())
+        .unwrap_or_default()
         .to_string_lossy()
         .to_string();
+    let file_path = path.to_string_lossy().to_string();
+    let relative_path = relative.to_string_lossy().to_string();
 
     if path.is_dir() {
         let mut children = Vec::new();
@@ -452,9 +2015,11 @@ fn build_file_tree(path: &Path, base_path: &Path) -> Result<ParsedPath> {
         if let Ok(entries) = fs::read_dir(path) {
             for entry in entries.flatten() {
                 let child_path = entry.path();
-                if !is_valid_path(&path) { continue; }
+                if !filter.is_valid(&child_path) {
+                    continue;
+                }
 
-                if let Ok(tree) = build_file_tree(&child_path, base_path) {
+                if let Ok(tree) = build_file_tree(&child_path, base_path, filter) {
                     total_size += match &tree {
                         ParsedPath::File { size, .. } => *size,
                         ParsedPath::Directory { size, .. } => *size,
@@ -483,6 +2048,7 @@ fn build_file_tree(path: &Path, base_path: &Path) -> Result<ParsedPath> {
     }
 }
 
+/// The selected `path` with its direct children; exclusions are matched below `path`.
 pub fn build_file_tree_shallow(path: &Path, base_path: &Path) -> Result<ParsedPath> {
     let name = path
         .file_name()
@@ -505,11 +2071,11 @@ pub fn build_file_tree_shallow(path: &Path, base_path: &Path) -> Result<ParsedPa
             for entry in entries.flatten() {
                 let child_path = entry.path();
 
-                if !is_valid_path(&child_path) {
+                if !is_valid_path(&child_path, path) {
                     continue;
                 }
 
-                let child_node = create_shallow_node(&child_path, base_path)?;
+                let child_node = create_shallow_node(&child_path, base_path, path)?;
 
                 match &child_node {
                     ParsedPath::File { size, .. } | ParsedPath::Directory { size, .. } => {
@@ -535,7 +2101,8 @@ pub fn build_file_tree_shallow(path: &Path, base_path: &Path) -> Result<ParsedPa
             size: current_level_size,
         })
     } else {
-        let metadata = build_file_metadata(path, base_path)?;
+        let relative_path = path.strip_prefix(base_path).unwrap_or(path);
+        let metadata = build_file_metadata(path, relative_path)?;
         Ok(ParsedPath::File {
             name,
             size: metadata.size,
@@ -545,7 +2112,9 @@ pub fn build_file_tree_shallow(path: &Path, base_path: &Path) -> Result<ParsedPa
     }
 }
 
-pub fn create_shallow_node(path: &Path, base_path: &Path) -> Result<ParsedPath> {
+/// A node for `path` without its children; `root` is the selected path exclusions are
+/// matched against when sizing a directory.
+pub fn create_shallow_node(path: &Path, base_path: &Path, root: &Path) -> Result<ParsedPath> {
     let name = path
         .file_name()
         .ok_or(anyhow::anyhow!("Failed to extract filename"))?
@@ -555,7 +2124,7 @@ pub fn create_shallow_node(path: &Path, base_path: &Path) -> Result<ParsedPath>
     let relative_path = path.strip_prefix(base_path).unwrap_or(path).to_string_lossy().to_string();
 
     if path.is_dir() {
-        let size = get_recursive_dir_size(path);
+        let size = get_recursive_dir_size(path, root);
 
         Ok(ParsedPath::Directory {
             name,
@@ -571,7 +2140,6 @@ pub fn create_shallow_node(path: &Path, base_path: &Path) -> Result<ParsedPath>
             relative_path,
             path: file_path,
             size: metadata.len(),
-
         })
     }
 }
@@ -612,6 +2180,16 @@ pub fn to_shallow_node(node: &ParsedPath) -> ParsedPath {
 // IO & Content Management
 // /////////////////////////////////////////////////////////////////////////////
 
+fn write_section(output_file: &mut File, section: &ContentSection) -> Result<()> {
+    writeln!(output_file, "===== {} =====", section.title)?;
+    output_file.write_all(section.body.as_bytes())?;
+    if !section.body.ends_with('\n') {
+        writeln!(output_file)?;
+    }
+    writeln!(output_file)?;
+    Ok(())
+}
+
 pub fn load_metadata(parse_dir: &Path) -> Result<ParseMetadata> {
     let path = parse_dir.join(METADATA_FILENAME);
     let file = File::open(path)?;
@@ -647,25 +2225,96 @@ pub fn save_metadata(path: &Path, metadata: &ParseMetadata) -> Result<()> {
     Ok(())
 }
 
-fn write_file_content(path: &Path, base_path: &Path, output_file: &mut File) -> Result<()> {
-    let mut file = File::open(&path).with_context(|| format!("Opening {}", path.display()))?;
-    let mut content = String::new();
-
-    file.read_to_string(&mut content)
-        .with_context(|| format!("File is not valid UTF-8: {}", path.display()))?;
+/// Writes the file's section to the output, transcoded to UTF-8. Returns `None` when the file
+/// was too large and only a skip marker was written.
+fn write_file_content(
+    path: &Path,
+    relative_path: &Path,
+    output_file: &mut File,
+    options: &ParseOptions,
+) -> Result<Option<DecodedText>> {
+    let size = fs::metadata(path)?.len();
+
+    // Notebooks are cut down to their cells before the size limit applies, as embedded images
+    // are what usually puts them over it.
+    let mut note = None;
+    let cells = if options.extract_notebooks && notebook::is_notebook(path) {
+        let bytes = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        let decoded = encoding::decode_bytes(&bytes);
+        match notebook::extract_notebook(&decoded.content, options.notebook_outputs) {
+            Ok(cells) => Some((cells, decoded)),
+            Err(e) => {
+                note = Some(truncate::marker(&format!(
+                    "raw notebook JSON kept: {:#}",
+                    e
+                )));
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    let relative_path = path.strip_prefix(base_path).unwrap_or(path);
+    let (body, source_encoding, had_bom) = if let Some((cells, decoded)) = cells {
+        let body = if cells.len() as u64 > options.max_file_size {
+            truncate::cut_text(
+                path,
+                &cells,
+                options.max_file_size,
+                &options.oversize_strategy,
+            )?
+        } else {
+            FileBody::Full(cells)
+        };
+        (body, decoded.encoding, decoded.had_bom)
+    } else if size > options.max_file_size {
+        let (source_encoding, bom_len) = encoding::detect_encoding(&read_sample(path)?);
+        let body = truncate::read_oversized(
+            path,
+            size,
+            options.max_file_size,
+            &options.oversize_strategy,
+            SourceEncoding {
+                encoding: source_encoding,
+                bom_len,
+            },
+        )?;
+        (body, source_encoding, bom_len > 0)
+    } else {
+        let bytes = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        let decoded = encoding::decode_bytes(&bytes);
+        (
+            FileBody::Full(decoded.content),
+            decoded.encoding,
+            decoded.had_bom,
+        )
+    };
 
     writeln!(output_file, "===== {} =====", relative_path.display())?;
+    if let Some(note) = &note {
+        output_file.write_all(note.as_bytes())?;
+    }
 
-    output_file.write_all(content.as_bytes())?;
+    let content = match body {
+        FileBody::Full(content) | FileBody::Truncated(content) => {
+            output_file.write_all(content.as_bytes())?;
+            Some(DecodedText {
+                content,
+                encoding: source_encoding,
+                had_bom,
+            })
+        }
+        FileBody::Skipped(note) => {
+            output_file.write_all(note.as_bytes())?;
+            None
+        }
+    };
 
     writeln!(output_file)?;
 
-    Ok(())
+    Ok(content)
 }
 
-
 // /////////////////////////////////////////////////////////////////////////////
 // System Actions (Open, Reveal)
 // /////////////////////////////////////////////////////////////////////////////
@@ -735,16 +2384,16 @@ fn emit_progress(
     );
 }
 
-fn count_text_files(paths: &[String]) -> Result<usize> {
+fn count_text_files(paths: &[String], filter: &PathFilter) -> Result<usize> {
     let mut count = 0;
     for path_str in paths {
         let path = Path::new(path_str);
-        if !is_valid_path(path) {
+        if !filter.is_valid(path) {
             continue;
         }
 
         if path.is_dir() {
-            count += count_text_files_in_dir(path)?;
+            count += count_text_files_in_dir(path, filter)?;
         } else if is_text_file(path) {
             count += 1;
         }
@@ -752,18 +2401,18 @@ fn count_text_files(paths: &[String]) -> Result<usize> {
     Ok(count)
 }
 
-fn count_text_files_in_dir(dir: &Path) -> Result<usize> {
+fn count_text_files_in_dir(dir: &Path, filter: &PathFilter) -> Result<usize> {
     let mut count = 0;
     if let Ok(entries) = fs::read_dir(dir) {
         for entry in entries.flatten() {
             let path = entry.path();
 
-            if !is_valid_path(&path) {
+            if !filter.is_valid(&path) {
                 continue;
             }
 
             if path.is_dir() {
-                count += count_text_files_in_dir(&path)?;
+                count += count_text_files_in_dir(&path, filter)?;
             } else if is_text_file(&path) {
                 count += 1;
             }
@@ -777,14 +2426,6 @@ fn is_text_file(path: &Path) -> bool {
         return false;
     }
 
-    if let Ok(metadata) = fs::metadata(path) {
-        if metadata.len() > MAX_FILE_SIZE {
-            return false;
-        }
-    } else {
-        return false;
-    }
-
     let mut file = match File::open(path) {
         Ok(f) => f,
         Err(_) => return false,
@@ -800,16 +2441,22 @@ fn is_text_file(path: &Path) -> bool {
         return true;
     }
 
-    !matches!(inspect(&buf[..sample_len]), ContentType::BINARY)
+    let sample = &buf[..sample_len];
+    !matches!(inspect(sample), ContentType::BINARY)
+        || encoding::detect_utf16_without_bom(sample).is_some()
 }
 
-fn build_file_metadata(path: &Path, base_path: &Path) -> Result<FileMetadata> {
+/// Start of the file, enough to detect its encoding without reading all of it.
+fn read_sample(path: &Path) -> Result<Vec<u8>> {
+    let file = File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let mut sample = Vec::new();
+    file.take(ENCODING_SAMPLE_SIZE).read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+fn build_file_metadata(path: &Path, relative_path: &Path) -> Result<FileMetadata> {
     let metadata = fs::metadata(path)?;
-    let relative_path = path
-        .strip_prefix(base_path)
-        .unwrap_or(path)
-        .to_string_lossy()
-        .to_string();
+    let relative_path = relative_path.to_string_lossy().to_string();
 
     Ok(FileMetadata {
         path: path.to_string_lossy().to_string(),
@@ -819,7 +2466,12 @@ fn build_file_metadata(path: &Path, base_path: &Path) -> Result<FileMetadata> {
             .to_string_lossy()
             .to_string(),
         size: metadata.len(),
-        relative_path
+        relative_path,
+        language: String::new(),
+        lines: 0,
+        tokens: 0,
+        encoding: String::new(),
+        had_bom: false,
     })
 }
 
@@ -877,4 +2529,400 @@ pub fn setup_tray<R: Runtime>(app: &App<R>) -> Result<(), tauri::Error> {
         .build(app)?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{git, write_file, zip_bytes, MockResponse, MockServer, TempDir};
+
+    const SHA: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// A server handing out the same archive for every download, with a `<name>-<sha>` top
+    /// directory and Gitea's repository API saying the default branch is `develop`.
+    fn archive_server() -> MockServer {
+        MockServer::start(|request| {
+            if request.path == "/api/v1/repos/o/r" {
+                return MockResponse::new(200, r#"{"default_branch":"develop"}"#);
+            }
+            let top = format!("r-{}/", SHA);
+            let readme = format!("{}README.md", top);
+            let archive = zip_bytes(&[(&top, b""), (&readme, b"hello")], None);
+            MockResponse::new(200, archive).header("ETag", "\"v1\"")
+        })
+    }
+
+    fn fetch(
+        location: &RepoLocation,
+        dest: &TempDir,
+        etag: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<Option<(FetchedTree, Option<String>)>> {
+        let handle = DownloadHandle::register(&Uuid::new_v4().to_string());
+        fetch_archive(
+            location,
+            dest.path(),
+            etag,
+            &DownloadOptions::default(),
+            &handle,
+            token,
+            |_| {},
+        )
+    }
+
+    #[test]
+    fn diffs_only_what_the_walk_would_reach() {
+        let dir = TempDir::new();
+        let root = dir.path().join("project");
+        write_file(&root, "src/main.rs", "");
+        write_file(&root, "build/out.txt", "");
+        let filter = PathFilter {
+            exclusions: Arc::new(Exclusions::new(&["build/".to_string()]).unwrap()),
+            ..PathFilter::default()
+        };
+
+        assert!(filter.accepts_below(&root, &root.join("src/main.rs")));
+        // Deleted files are still judged by their path.
+        assert!(filter.accepts_below(&root, &root.join("src/deleted.rs")));
+        assert!(!filter.accepts_below(&root, &root.join("build/out.txt")));
+        assert!(!filter.accepts_below(&root, &root.join(".github/ci.yml")));
+        assert!(!filter.accepts_below(&root, &dir.path().join("elsewhere.rs")));
+    }
+
+    #[test]
+    fn names_sources_without_moving_them() {
+        let dir = TempDir::new();
+        write_file(dir.path(), "a/src/lib.rs", "");
+        write_file(dir.path(), "b/src/main.rs", "");
+        let first = dir.path().join("a/src");
+        let second = dir.path().join("b/src");
+        let mut summaries: Vec<SourceSummary> = [&first, &second]
+            .iter()
+            .map(|path| SourceSummary {
+                name: file_name_of(path),
+                path: Some(path.display().to_string()),
+                url: None,
+                git_ref: None,
+                commit_sha: None,
+                worktree: None,
+            })
+            .collect();
+        name_sources_uniquely(&mut summaries);
+        assert_eq!(summaries[1].name, "src-2");
+
+        let base = BasePath::of(&second, Some(&summaries[1].name));
+        assert_eq!(base.relative(&second), Path::new("src-2"));
+        assert_eq!(
+            base.relative(&second.join("main.rs")),
+            Path::new("src-2/main.rs")
+        );
+
+        // A file keeps its own name unless it had to be renamed.
+        let file = first.join("lib.rs");
+        assert_eq!(
+            BasePath::of(&file, Some("lib.rs")).relative(&file),
+            Path::new("lib.rs")
+        );
+        assert_eq!(
+            BasePath::of(&file, Some("lib.rs-2")).relative(&file),
+            Path::new("lib.rs-2/lib.rs")
+        );
+        assert_eq!(
+            BasePath::of(&first, None).relative(&file),
+            Path::new("lib.rs")
+        );
+    }
+
+    #[test]
+    fn extracts_notebooks_before_the_size_limit() {
+        let dir = TempDir::new();
+        let image = "A".repeat(4096);
+        let notebook = format!(
+            r#"{{"cells": [{{"cell_type": "code", "source": "plot()", "outputs": [
+                {{"output_type": "display_data", "data": {{"image/png": "{}"}}}}
+            ]}}]}}"#,
+            image
+        );
+        write_file(dir.path(), "plot.ipynb", &notebook);
+        write_file(dir.path(), "broken.ipynb", "{ not json");
+        let options = ParseOptions {
+            max_file_size: 1024,
+            notebook_outputs: true,
+            ..ParseOptions::default()
+        };
+
+        let output_path = dir.path().join("content.txt");
+        let mut output = File::create(&output_path).unwrap();
+        let written = write_file_content(
+            &dir.path().join("plot.ipynb"),
+            Path::new("plot.ipynb"),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+        assert!(written.is_some());
+        write_file_content(
+            &dir.path().join("broken.ipynb"),
+            Path::new("broken.ipynb"),
+            &mut output,
+            &options,
+        )
+        .unwrap();
+        drop(output);
+
+        let content = fs::read_to_string(&output_path).unwrap();
+        assert!(content.contains("plot()\n\n--- output ---\n[image/png output omitted]"));
+        assert!(!content.contains(&image));
+        assert!(content.contains(
+            "===== broken.ipynb =====\n... [raw notebook JSON kept: Notebook is not valid JSON"
+        ));
+    }
+
+    #[test]
+    fn matches_exclusions_below_each_parsed_root() {
+        let dir = TempDir::new();
+        let root = dir.path().join("target/project");
+        write_file(&root, "src/main.rs", "");
+        write_file(&root, "target/out.txt", "");
+        write_file(dir.path(), "dist/app.js", "");
+        let dist = dir.path().join("dist");
+        let options = ParseOptions {
+            exclusions: Some(vec!["target/".to_string(), "dist/".to_string()]),
+            linguist_attributes: false,
+            ..ParseOptions::default()
+        };
+        let paths = [root.display().to_string(), dist.display().to_string()];
+        let filter = PathFilter::new(&paths, &options).unwrap();
+
+        assert!(filter.is_valid(&root.join("src/main.rs")));
+        assert!(!filter.is_valid(&root.join("target")));
+        // An excluded directory the user selected is still parsed.
+        assert!(filter.is_valid(&dist));
+        assert!(filter.is_valid(&dist.join("app.js")));
+    }
+
+    #[test]
+    fn attributes_start_at_the_worktree_or_fetched_files() {
+        let dir = TempDir::new();
+        let temp_repos_root = dir.path().join("temp-repos");
+        let fetched = temp_repos_root.join("id/repo");
+        write_file(&fetched, "pkg/lib.rs", "");
+        assert_eq!(
+            attributes_root(&fetched.join("pkg"), &temp_repos_root),
+            fetched
+        );
+
+        let plain = dir.path().join("plain");
+        write_file(&plain, "pkg/lib.rs", "");
+        assert_eq!(
+            attributes_root(&plain.join("pkg/lib.rs"), &temp_repos_root),
+            plain.join("pkg")
+        );
+
+        let worktree = dir.path().join("worktree");
+        write_file(&worktree, "pkg/lib.rs", "");
+        git(&worktree, &["init", "-q"]);
+        let root = attributes_root(&worktree.join("pkg"), &temp_repos_root);
+        assert_eq!(root, worktree);
+    }
+
+    #[test]
+    fn downloads_provider_archives_from_browse_urls() {
+        let cases = [
+            (
+                Provider::GitHub,
+                "/o/r/tree/main/src",
+                "/o/r/archive/main.zip",
+            ),
+            (
+                Provider::GitLab,
+                "/o/r/-/tree/main/src",
+                "/o/r/-/archive/main/r-main.zip",
+            ),
+            (
+                Provider::Bitbucket,
+                "/o/r/src/main/src",
+                "/o/r/get/main.zip",
+            ),
+            (
+                Provider::Gitea,
+                "/o/r/src/branch/main/src",
+                "/o/r/archive/main.zip",
+            ),
+        ];
+        for (provider, browse_path, archive_path) in cases {
+            let server = archive_server();
+            let url = format!("{}{}", server.base_url(), browse_path);
+            let location = remote::parse_repo_url(&url, None, Some(provider)).unwrap();
+            assert_eq!(location.repo_path, "o/r", "{:?}", provider);
+            assert_eq!(
+                location.reference.as_deref(),
+                Some("main"),
+                "{:?}",
+                provider
+            );
+            assert_eq!(location.subdir.as_deref(), Some("src"), "{:?}", provider);
+
+            let dest = TempDir::new();
+            let result = fetch(&location, &dest, None, None);
+            let (tree, etag) = result.unwrap().expect("a fresh download");
+            assert_eq!(
+                fs::read_to_string(tree.files.join("README.md")).unwrap(),
+                "hello"
+            );
+            assert_eq!(tree.commit.as_deref(), Some(SHA), "{:?}", provider);
+            assert_eq!(etag.as_deref(), Some("\"v1\""));
+
+            let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+            assert_eq!(paths, [archive_path], "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn resolves_the_default_branch() {
+        let cases = [
+            (Provider::GitHub, vec!["/o/r/archive/HEAD.zip"]),
+            (Provider::GitLab, vec!["/o/r/-/archive/HEAD/r-HEAD.zip"]),
+            (Provider::Bitbucket, vec!["/o/r/get/HEAD.zip"]),
+            // Gitea has no HEAD alias, so its API is asked first.
+            (
+                Provider::Gitea,
+                vec!["/api/v1/repos/o/r", "/o/r/archive/develop.zip"],
+            ),
+        ];
+        for (provider, expected) in cases {
+            let server = archive_server();
+            let url = format!("{}/o/r", server.base_url());
+            let location = remote::parse_repo_url(&url, None, Some(provider)).unwrap();
+
+            let dest = TempDir::new();
+            let result = fetch(&location, &dest, None, None);
+            assert!(result.unwrap().is_some(), "{:?}", provider);
+            let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+            assert_eq!(paths, expected, "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn tokens_go_to_the_api_endpoints() {
+        let cases = [
+            (
+                Provider::GitHub,
+                "/api/v3/repos/o/r/zipball/main",
+                "authorization",
+                "Bearer secret",
+            ),
+            (
+                Provider::GitLab,
+                "/api/v4/projects/o%2Fr/repository/archive.zip?sha=main",
+                "authorization",
+                "Bearer secret",
+            ),
+            (
+                Provider::Bitbucket,
+                "/o/r/get/main.zip",
+                "authorization",
+                "Bearer secret",
+            ),
+            (
+                Provider::Gitea,
+                "/api/v1/repos/o/r/archive/main.zip",
+                "authorization",
+                "token secret",
+            ),
+        ];
+        for (provider, archive_path, header, value) in cases {
+            let server = archive_server();
+            let url = format!("{}/o/r", server.base_url());
+            let location = remote::parse_repo_url(&url, Some("main"), Some(provider)).unwrap();
+
+            let dest = TempDir::new();
+            let result = fetch(&location, &dest, None, Some("secret"));
+            assert!(result.unwrap().is_some(), "{:?}", provider);
+            let requests = server.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].path, archive_path, "{:?}", provider);
+            assert_eq!(requests[0].header(header), Some(value), "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn tokens_do_not_follow_redirects_to_other_hosts() {
+        let other = archive_server();
+        for provider in [Provider::GitHub, Provider::GitLab, Provider::Gitea] {
+            let target = other.base_url().to_string();
+            let server = MockServer::start(move |request| {
+                let location = format!("{}{}", target, request.path);
+                MockResponse::new(302, "").header("Location", &location)
+            });
+            let url = format!("{}/o/r", server.base_url());
+            let location = remote::parse_repo_url(&url, Some("main"), Some(provider)).unwrap();
+
+            let dest = TempDir::new();
+            let result = fetch(&location, &dest, None, Some("secret"));
+            assert!(result.unwrap().is_some(), "{:?}", provider);
+            assert!(server.requests()[0].header("authorization").is_some());
+        }
+        assert_eq!(other.requests().len(), 3);
+
+        for request in other.requests() {
+            assert!(
+                request
+                    .headers
+                    .iter()
+                    .all(|(_, value)| !value.contains("secret")),
+                "{:?}",
+                request.headers
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_refs_in_archive_urls() {
+        let server = archive_server();
+        let url = format!("{}/o/r", server.base_url());
+        let location =
+            remote::parse_repo_url(&url, Some("release/1.0#2"), Some(Provider::GitHub)).unwrap();
+
+        let dest = TempDir::new();
+        fetch(&location, &dest, None, None).unwrap();
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/o/r/archive/release%2F1.0%232.zip"]);
+    }
+
+    #[test]
+    fn current_etag_is_not_modified() {
+        for provider in [
+            Provider::GitHub,
+            Provider::GitLab,
+            Provider::Bitbucket,
+            Provider::Gitea,
+        ] {
+            let server = MockServer::start(|request| match request.header("if-none-match") {
+                Some("\"v1\"") => MockResponse::new(304, ""),
+                _ => MockResponse::new(200, zip_bytes(&[("r/a.txt", b"a")], None))
+                    .header("ETag", "\"v2\""),
+            });
+            let url = format!("{}/o/r", server.base_url());
+            let location = remote::parse_repo_url(&url, Some("main"), Some(provider)).unwrap();
+
+            let dest = TempDir::new();
+            let current = fetch(&location, &dest, Some("\"v1\""), None);
+            assert!(current.unwrap().is_none(), "{:?}", provider);
+
+            let dest = TempDir::new();
+            let stale = fetch(&location, &dest, Some("\"v0\""), None);
+            let (_, etag) = stale.unwrap().expect("a changed archive");
+            assert_eq!(etag.as_deref(), Some("\"v2\""), "{:?}", provider);
+
+            let sent: Vec<_> = server
+                .requests()
+                .iter()
+                .map(|r| r.header("if-none-match").map(str::to_string))
+                .collect();
+            assert_eq!(
+                sent,
+                [Some("\"v1\"".to_string()), Some("\"v0\"".to_string())]
+            );
+        }
+    }
+}
@@ -9,6 +9,7 @@ export const gitRepoSchema = z
   .refine((url) => {
     try {
       const { pathname } = new URL(url);
+      if (/\.(zip|tar|tar\.gz|tgz|tar\.xz|txz)$/i.test(pathname)) return true;
       const segments = pathname.split('/').filter(Boolean);
       return segments.length >= 2;
     } catch {
@@ -17,10 +18,10 @@ export const gitRepoSchema = z
   }, 'Incomplete repository path. Example: https://github.com/user/repo')
 
   .refine(
-    (url) => !/\/blob\/|\/tree\/|\/commit\//.test(url),
-    'Please provide the root repository URL (remove /tree/, /blob/, etc.)'
+    (url) => !/\/blob\/|\/commit\//.test(url),
+    'Please provide a repository or folder URL (remove /blob/, /commit/, etc.)'
   )
-  .refine((url) => !/\.(zip|tar|gz|rar|7z)$/i.test(url), 'Cannot clone archive files directly');
+  .refine((url) => !/\.(rar|7z)$/i.test(url), 'Only .zip, .tar, .tar.gz and .tar.xz archives are supported');
 
 export const renameSchema = z
   .string()
@@ -17,9 +17,9 @@ export const getPreviewTreeNodes = async (paths: string[]): Promise<FileTree[]>
   });
 };
 
-export const expandNode = async (path: string): Promise<FileTree[]> => {
+export const expandNode = async (path: string, root?: string): Promise<FileTree[]> => {
   try {
-    const children = await invoke<FileTree[]>('expand_folder', { path });
+    const children = await invoke<FileTree[]>('expand_folder', { path, root: root ?? null });
 
     return children.map((n) => ({ ...n, isExpanded: false, selected: true }));
   } catch (e) {